@@ -0,0 +1,65 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bruser::dom::{AttributeMap, ElementData};
+use bruser::style::Selector;
+use bruser::styled_dom;
+
+const ITERATIONS: u32 = 1_000_000;
+
+// What matching cost before the fast path: once the tag and id matched, the
+// element's classes were collected into a set and checked, even for a
+// selector with none.
+fn matches_general(selector: &Selector, element_data: &ElementData) -> bool {
+    styled_dom::matches(selector, element_data.into(), &[]) && {
+        let element_classes = element_data.classes();
+        selector
+            .class
+            .iter()
+            .all(|class| element_classes.contains(&**class))
+    }
+}
+
+fn matches_fast(selector: &Selector, element_data: &ElementData) -> bool {
+    styled_dom::matches(selector, element_data.into(), &[])
+}
+
+fn time<F>(selector: &Selector, element_data: &ElementData, matches: F) -> Duration
+where
+    F: Fn(&Selector, &ElementData) -> bool,
+{
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(matches(black_box(selector), black_box(element_data)));
+    }
+    start.elapsed() / ITERATIONS
+}
+
+// Times the same tag-only selector through the general path and the fast
+// path, so the difference is what skipping the classes saves.
+fn bench(name: &str, selector: &str, element_data: &ElementData) {
+    let selector = Selector::parse(selector).unwrap();
+    assert!(selector.class.is_empty() && selector.id.is_none());
+
+    let general = time(&selector, element_data, matches_general);
+    let fast = time(&selector, element_data, matches_fast);
+
+    println!("matches with {name}: {general:?} general, {fast:?} fast path per element");
+}
+
+fn main() {
+    let element_data = ElementData::new(
+        "li".to_string(),
+        AttributeMap::from([
+            ("id".to_string(), "first".to_string()),
+            ("class".to_string(), "item done highlighted".to_string()),
+        ]),
+    );
+
+    bench("a matching tag-only selector", "li", &element_data);
+    bench(
+        "a tag-only selector that does not match",
+        "ul",
+        &element_data,
+    );
+}
@@ -0,0 +1,24 @@
+use std::time::Instant;
+
+use bruser::{css, html, styled_dom};
+
+const ITEMS: usize = 5000;
+const ITERATIONS: u32 = 20;
+
+fn main() {
+    let items = "<li class='item'>item</li>".repeat(ITEMS);
+    let root = html::parse(format!("<ul id='list'>{items}</ul>")).unwrap();
+    let stylesheet = css::parse(
+        "ul { display: block; } li { display: list-item; } .item { color: #333333; } li.item { margin-top: 4px; } #list { padding: 0; }"
+            .to_string(),
+    )
+    .unwrap();
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        styled_dom::style_tree(&root, &stylesheet);
+    }
+    let elapsed = start.elapsed() / ITERATIONS;
+
+    println!("style_tree over {ITEMS} identical list items: {elapsed:?} per iteration");
+}
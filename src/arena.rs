@@ -0,0 +1,290 @@
+use crate::dom::{ElementData, Node, NodeType};
+
+// A handle to a node in an `Arena`. It is only meaningful for the arena that
+// returned it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+// A node with links to its neighbours instead of owned children, so the tree
+// can be walked upwards and sideways as well as down.
+#[derive(Debug, PartialEq)]
+pub struct NodeData {
+    pub node_type: NodeType,
+    parent: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+    previous_sibling: Option<NodeId>,
+    next_sibling: Option<NodeId>,
+}
+
+// The nodes of a tree in one `Vec`, addressed by `NodeId`. A detached node
+// keeps its slot, so ids stay valid after any mutation.
+#[derive(Debug, Default, PartialEq)]
+pub struct Arena {
+    nodes: Vec<NodeData>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena { nodes: Vec::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Adds a node without a parent or children.
+    pub fn new_node(&mut self, node_type: NodeType) -> NodeId {
+        self.nodes.push(NodeData {
+            node_type,
+            parent: None,
+            first_child: None,
+            last_child: None,
+            previous_sibling: None,
+            next_sibling: None,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    pub fn get(&self, id: NodeId) -> &NodeData {
+        &self.nodes[id.0]
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut NodeData {
+        &mut self.nodes[id.0]
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id).parent
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id).first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id).last_child
+    }
+
+    pub fn previous_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id).previous_sibling
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.get(id).next_sibling
+    }
+
+    pub fn children(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.first_child(id), |&child| self.next_sibling(child))
+    }
+
+    // The parent, its parent and so on up to the root, nearest first.
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), |&ancestor| self.parent(ancestor))
+    }
+
+    // Unlinks the node from its parent and siblings. Its own children stay
+    // with it.
+    pub fn detach(&mut self, id: NodeId) {
+        let NodeData {
+            parent,
+            previous_sibling,
+            next_sibling,
+            ..
+        } = *self.get(id);
+        match previous_sibling {
+            Some(previous) => self.get_mut(previous).next_sibling = next_sibling,
+            None => {
+                if let Some(parent) = parent {
+                    self.get_mut(parent).first_child = next_sibling;
+                }
+            }
+        }
+        match next_sibling {
+            Some(next) => self.get_mut(next).previous_sibling = previous_sibling,
+            None => {
+                if let Some(parent) = parent {
+                    self.get_mut(parent).last_child = previous_sibling;
+                }
+            }
+        }
+        let node = self.get_mut(id);
+        node.parent = None;
+        node.previous_sibling = None;
+        node.next_sibling = None;
+    }
+
+    // Moves `child` to the end of the children of `parent`, detaching it
+    // from where it was. Panics if `child` is `parent` or one of its
+    // ancestors, as that would make a cycle.
+    pub fn append_child(&mut self, parent: NodeId, child: NodeId) {
+        assert!(
+            child != parent && self.ancestors(parent).all(|ancestor| ancestor != child),
+            "cannot append a node to itself or its descendant"
+        );
+        self.detach(child);
+        self.link_last_child(parent, child);
+    }
+
+    // Links a detached `child` after the last child of `parent`.
+    fn link_last_child(&mut self, parent: NodeId, child: NodeId) {
+        let previous = self.last_child(parent);
+        match previous {
+            Some(previous) => self.get_mut(previous).next_sibling = Some(child),
+            None => self.get_mut(parent).first_child = Some(child),
+        }
+        self.get_mut(parent).last_child = Some(child);
+        let node = self.get_mut(child);
+        node.parent = Some(parent);
+        node.previous_sibling = previous;
+    }
+
+    // Copies the subtree under `id` back into an owned `Node`.
+    pub fn to_node(&self, id: NodeId) -> Node {
+        Node {
+            children: self.children(id).map(|child| self.to_node(child)).collect(),
+            node_type: self.get(id).node_type.clone(),
+        }
+    }
+}
+
+impl NodeData {
+    pub fn as_element(&self) -> Option<&ElementData> {
+        match self.node_type {
+            NodeType::Element(ref element_data) => Some(element_data),
+            NodeType::Text(_) | NodeType::Comment(_) => None,
+        }
+    }
+}
+
+impl Node {
+    // Moves the tree into an arena, returning the id of its root. Nodes are
+    // allocated in document order, with a stack instead of recursion. A new
+    // node cannot be an ancestor, so it is linked without the cycle check of
+    // `append_child`.
+    pub fn into_arena(self) -> (Arena, NodeId) {
+        let mut arena = Arena::new();
+        let mut stack = Vec::from([(self, None)]);
+        let mut root = None;
+        while let Some((node, parent)) = stack.pop() {
+            let id = arena.new_node(node.node_type);
+            match parent {
+                Some(parent) => arena.link_last_child(parent, id),
+                None => root = Some(id),
+            }
+            stack.extend(
+                node.children
+                    .into_iter()
+                    .rev()
+                    .map(|child| (child, Some(id))),
+            );
+        }
+        (arena, root.unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+    extern crate speculate;
+
+    use rstest::*;
+    use speculate::speculate;
+
+    use super::*;
+    use crate::dom::AttributeMap;
+    use crate::html;
+
+    speculate! {
+        describe "'into_arena' links each node to its parent and siblings" {
+            #[rstest]
+            fn test_navigation() {
+                let root = html::parse("<ul><li id='a'>one</li><!-- x --><li id='b'><em>two</em></li></ul>".to_string()).unwrap();
+                let (arena, ul) = root.into_arena();
+                let id = |node: NodeId| arena.get(node).as_element().and_then(|element_data| element_data.id()).map(String::as_str);
+
+                let children: Vec<NodeId> = arena.children(ul).collect();
+                assert_eq!(children.len(), 3);
+                let (a, comment, b) = (children[0], children[1], children[2]);
+
+                assert_eq!(arena.len(), 7);
+                assert_eq!(arena.parent(ul), None);
+                assert_eq!(id(a), Some("a"));
+                assert_eq!(arena.get(comment).node_type, NodeType::Comment(" x ".to_string()));
+                assert_eq!(id(b), Some("b"));
+                assert_eq!(arena.first_child(ul), Some(a));
+                assert_eq!(arena.last_child(ul), Some(b));
+                assert_eq!(arena.next_sibling(a), Some(comment));
+                assert_eq!(arena.next_sibling(b), None);
+                assert_eq!(arena.previous_sibling(b), Some(comment));
+                assert_eq!(arena.previous_sibling(a), None);
+
+                let em = arena.first_child(b).unwrap();
+                let text = arena.first_child(em).unwrap();
+                assert_eq!(arena.get(text).node_type, NodeType::Text("two".to_string()));
+                assert_eq!(arena.parent(text), Some(em));
+                assert_eq!(arena.ancestors(text).collect::<Vec<_>>(), Vec::from([em, b, ul]));
+            }
+
+            #[rstest]
+            fn test_round_trip() {
+                let root = html::parse("<div class='x'><p>a<br></br>b</p><!--c--></div>".to_string()).unwrap();
+                let expected = root.to_html();
+                let (arena, id) = root.into_arena();
+
+                assert_eq!(arena.to_node(id).to_html(), expected);
+            }
+
+            #[rstest]
+            fn test_deep_tree() {
+                let mut root = Node::text("leaf".to_string());
+                for _ in 0..100_000 {
+                    root = Node::element("div".to_string(), AttributeMap::new(), Vec::from([root]));
+                }
+                let (arena, id) = root.into_arena();
+
+                assert_eq!(arena.len(), 100_001);
+                assert_eq!(arena.first_child(id).and_then(|child| arena.parent(child)), Some(id));
+            }
+        }
+
+        describe "'Arena' moves nodes while keeping the links consistent" {
+            #[rstest]
+            fn test_detach_and_append() {
+                let root = html::parse("<div><p id='a'></p><p id='b'></p><p id='c'></p></div>".to_string()).unwrap();
+                let (mut arena, div) = root.into_arena();
+                let children: Vec<NodeId> = arena.children(div).collect();
+                let (a, b, c) = (children[0], children[1], children[2]);
+
+                arena.detach(b);
+                assert_eq!(arena.children(div).collect::<Vec<_>>(), Vec::from([a, c]));
+                assert_eq!(arena.next_sibling(a), Some(c));
+                assert_eq!(arena.previous_sibling(c), Some(a));
+                assert_eq!(arena.parent(b), None);
+
+                arena.append_child(c, b);
+                arena.append_child(div, a);
+                assert_eq!(arena.children(div).collect::<Vec<_>>(), Vec::from([c, a]));
+                assert_eq!(arena.first_child(div), Some(c));
+                assert_eq!(arena.last_child(div), Some(a));
+                assert_eq!(arena.previous_sibling(c), None);
+                assert_eq!(arena.ancestors(b).collect::<Vec<_>>(), Vec::from([c, div]));
+                assert_eq!(arena.to_node(div).to_html(), "<div><p id=\"c\"><p id=\"b\"></p></p><p id=\"a\"></p></div>");
+            }
+
+            #[rstest]
+            #[should_panic(expected = "cannot append a node to itself or its descendant")]
+            fn test_append_ancestor_panics() {
+                let root = html::parse("<div><p></p></div>".to_string()).unwrap();
+                let (mut arena, div) = root.into_arena();
+                let p = arena.first_child(div).unwrap();
+
+                arena.append_child(p, div);
+            }
+        }
+    }
+}
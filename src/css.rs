@@ -1,83 +1,788 @@
+use std::fmt;
+
 use crate::{
-    parser::Parser,
-    style::{Color, Declaration, Rule, Selector, StyleSheet, Unit, Value},
+    css_tokenizer::{tokenize_with_offsets, CssToken},
+    parser::{ParserLimits, Warning},
+    style::{
+        CalcExpr, Color, ColorScheme, Combinator, Declaration, MediaFeature, MediaQuery,
+        NthFormula, Orientation, PseudoClass, PseudoElement, Rule, Selector, StyleSheet, Unit,
+        Value, DEFAULT_FONT_SIZE, FONT_SIZE_KEYWORDS,
+    },
 };
 
+#[derive(Debug, PartialEq)]
+pub struct CssParseError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
 struct CSSParser {
-    base: Parser,
+    input: String,
+    tokens: Vec<(CssToken, usize)>,
+    pos: usize,
+    diagnostics: bool,
+    warnings: Vec<Warning>,
+    limits: ParserLimits,
 }
 
-fn parse_value(value: String) -> Value {
-    if value.starts_with('#') {
-        assert!(value.len() == 7);
-        let r = u8::from_str_radix(&value[1..=2], 16).unwrap();
-        let g = u8::from_str_radix(&value[3..=4], 16).unwrap();
-        let b = u8::from_str_radix(&value[5..=6], 16).unwrap();
-        return Value::color(r, g, b);
-    }
-
-    if ('0'..='9').contains(&value.chars().next().unwrap()) {
-        let (num, unit) = if value.ends_with("px") {
-            ((value[..value.len() - 2]).parse::<f32>().unwrap(), Unit::Px)
-        } else if value.ends_with("%") {
-            (
-                (value[..value.len() - 1]).parse::<f32>().unwrap(),
-                Unit::Percent,
-            )
-        } else if value.ends_with("rem") {
-            (
-                (value[..value.len() - 3]).parse::<f32>().unwrap(),
-                Unit::Rem,
+// How deeply the value being parsed nests, through `calc()` parentheses and
+// `var()` fallbacks. Each level recurses, so it is bounded by `max_depth`
+// to fail on hostile input instead of overflowing the stack.
+#[derive(Clone, Copy)]
+struct Nesting {
+    depth: usize,
+    max_depth: usize,
+}
+
+// Properties that `parse_with_diagnostics` does not warn about.
+const KNOWN_PROPERTIES: &[&str] = &[
+    "align-items",
+    "align-self",
+    "background",
+    "background-color",
+    "background-image",
+    "border",
+    "border-bottom",
+    "border-color",
+    "border-left",
+    "border-radius",
+    "border-right",
+    "border-style",
+    "border-top",
+    "border-width",
+    "bottom",
+    "box-sizing",
+    "color",
+    "content",
+    "cursor",
+    "display",
+    "flex",
+    "flex-direction",
+    "flex-wrap",
+    "float",
+    "font",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-variant",
+    "font-weight",
+    "gap",
+    "height",
+    "justify-content",
+    "left",
+    "letter-spacing",
+    "line-height",
+    "list-style",
+    "margin",
+    "margin-bottom",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "max-height",
+    "max-width",
+    "min-height",
+    "min-width",
+    "opacity",
+    "outline",
+    "overflow",
+    "overflow-wrap",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "position",
+    "right",
+    "text-align",
+    "text-decoration",
+    "text-transform",
+    "top",
+    "transform",
+    "transition",
+    "vertical-align",
+    "visibility",
+    "white-space",
+    "width",
+    "word-wrap",
+    "z-index",
+];
+
+type ParseResult<T> = Result<T, CssParseError>;
+
+impl fmt::Display for CssParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}:{}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for CssParseError {}
+
+fn trim_whitespace(tokens: &[CssToken]) -> &[CssToken] {
+    let is_blank = |token: &CssToken| matches!(token, CssToken::Whitespace | CssToken::Comment(_));
+    let start = tokens
+        .iter()
+        .position(|token| !is_blank(token))
+        .unwrap_or(tokens.len());
+    let end = tokens
+        .iter()
+        .rposition(|token| !is_blank(token))
+        .map_or(start, |i| i + 1);
+    &tokens[start..end]
+}
+
+fn parse_color(hex: &str) -> Result<Value, String> {
+    let channel = |i: usize| {
+        hex.get(i..i + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+    };
+    match (hex.len(), channel(0), channel(2), channel(4)) {
+        (6, Some(r), Some(g), Some(b)) => Ok(Value::color(r, g, b)),
+        _ => Err(format!("invalid color '#{hex}'")),
+    }
+}
+
+fn scale_channel(x: f32, max: f32) -> u8 {
+    (x / max * 255.0).clamp(0.0, 255.0).round() as u8
+}
+
+fn hue_to_rgb(p: f32, q: f32, t: f32) -> f32 {
+    let t = t.rem_euclid(1.0);
+    if t < 1.0 / 6.0 {
+        p + (q - p) * 6.0 * t
+    } else if t < 1.0 / 2.0 {
+        q
+    } else if t < 2.0 / 3.0 {
+        p + (q - p) * (2.0 / 3.0 - t) * 6.0
+    } else {
+        p
+    }
+}
+
+// Parses the arguments of `rgb()`, `rgba()`, `hsl()` and `hsla()`. They may be
+// separated by commas or by whitespace with a `/` before the alpha. Channels
+// out of range are clamped.
+fn parse_color_function(name: &str, tokens: &[CssToken]) -> Result<Value, String> {
+    let name = name.to_ascii_lowercase();
+    let args: Vec<(f32, &str)> = tokens
+        .iter()
+        .filter(|token| {
+            !matches!(
+                token,
+                CssToken::Whitespace
+                    | CssToken::Comment(_)
+                    | CssToken::Comma
+                    | CssToken::Delim('/')
             )
-        } else if value.ends_with("em") {
-            ((value[..value.len() - 2]).parse::<f32>().unwrap(), Unit::Em)
+        })
+        .map(|token| match token {
+            CssToken::Number(x, unit) => Ok((*x, unit.as_str())),
+            token => Err(format!("unexpected '{token}' in {name}()")),
+        })
+        .collect::<Result<_, _>>()?;
+    if !(3..=4).contains(&args.len()) {
+        return Err(format!("expected 3 or 4 arguments in {name}()"));
+    }
+    let invalid = |(x, unit): (f32, &str)| format!("unexpected '{x}{unit}' in {name}()");
+
+    let alpha = match args.get(3) {
+        None => 255,
+        Some(&(x, "")) => scale_channel(x, 1.0),
+        Some(&(x, "%")) => scale_channel(x, 100.0),
+        Some(&arg) => return Err(invalid(arg)),
+    };
+
+    let (r, g, b) = if name.starts_with("rgb") {
+        let channel = |arg: (f32, &str)| match arg {
+            (x, "") => Ok(scale_channel(x, 255.0)),
+            (x, "%") => Ok(scale_channel(x, 100.0)),
+            arg => Err(invalid(arg)),
+        };
+        (channel(args[0])?, channel(args[1])?, channel(args[2])?)
+    } else {
+        let hue = match args[0] {
+            (x, "" | "deg") => x / 360.0,
+            arg => return Err(invalid(arg)),
+        };
+        let percent = |arg: (f32, &str)| match arg {
+            (x, "%") => Ok((x / 100.0).clamp(0.0, 1.0)),
+            arg => Err(invalid(arg)),
+        };
+        let (saturation, lightness) = (percent(args[1])?, percent(args[2])?);
+
+        let q = if lightness < 0.5 {
+            lightness * (1.0 + saturation)
         } else {
-            ((value).parse::<f32>().unwrap(), Unit::None)
+            lightness + saturation - lightness * saturation
+        };
+        let p = 2.0 * lightness - q;
+        (
+            scale_channel(hue_to_rgb(p, q, hue + 1.0 / 3.0), 1.0),
+            scale_channel(hue_to_rgb(p, q, hue), 1.0),
+            scale_channel(hue_to_rgb(p, q, hue - 1.0 / 3.0), 1.0),
+        )
+    };
+
+    Ok(Value::Color(Color::rgba(r, g, b, alpha)))
+}
+
+fn parse_unit(unit: &str) -> Result<Unit, String> {
+    match unit {
+        "px" => Ok(Unit::Px),
+        "%" => Ok(Unit::Percent),
+        "em" => Ok(Unit::Em),
+        "rem" => Ok(Unit::Rem),
+        "vw" => Ok(Unit::Vw),
+        "vh" => Ok(Unit::Vh),
+        "" => Ok(Unit::None),
+        _ => Err(format!("unknown unit '{unit}'")),
+    }
+}
+
+// Parses the `an+b` argument of `:nth-child()` and `:nth-of-type()`, named
+// by `function`, including the `odd` and `even` keywords. `a` and `b` may
+// each be left out, as in `n+2` or `3`.
+fn parse_nth_formula(function: &str, text: &str) -> Result<NthFormula, String> {
+    let formula: String = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let invalid = || format!("invalid :{function}() argument '{}'", text.trim());
+
+    match formula.as_str() {
+        "odd" => return Ok(NthFormula::new(2, 1)),
+        "even" => return Ok(NthFormula::new(2, 0)),
+        _ => {}
+    }
+
+    let Some((a, b)) = formula.split_once('n') else {
+        return formula
+            .parse()
+            .map(|b| NthFormula::new(0, b))
+            .map_err(|_| invalid());
+    };
+    let a = match a {
+        "" | "+" => 1,
+        "-" => -1,
+        a => a.parse().map_err(|_| invalid())?,
+    };
+    let b = match b {
+        "" => 0,
+        b if b.starts_with(['+', '-']) => b.parse().map_err(|_| invalid())?,
+        _ => return Err(invalid()),
+    };
+    Ok(NthFormula::new(a, b))
+}
+
+fn split_commas(tokens: &[CssToken]) -> Vec<&[CssToken]> {
+    let mut parts = Vec::new();
+    let mut depth: usize = 0;
+    let mut start = 0;
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            CssToken::Function(_) | CssToken::OpenParen | CssToken::OpenBracket => depth += 1,
+            CssToken::CloseParen | CssToken::CloseBracket => depth = depth.saturating_sub(1),
+            CssToken::Comma if depth == 0 => {
+                parts.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&tokens[start..]);
+
+    parts
+}
+
+impl Nesting {
+    fn new(max_depth: usize) -> Self {
+        Nesting {
+            depth: 0,
+            max_depth,
+        }
+    }
+
+    fn enter(self) -> Result<Self, String> {
+        if self.depth >= self.max_depth {
+            return Err(format!("values are nested deeper than {}", self.max_depth));
+        }
+        Ok(Nesting {
+            depth: self.depth + 1,
+            ..self
+        })
+    }
+}
+
+// Parses the inside of `calc()`. `*` and `/` bind tighter than `+` and `-`,
+// and parentheses or a nested `calc(` group a sub-expression.
+fn parse_calc(tokens: &[CssToken], nesting: Nesting) -> Result<CalcExpr, String> {
+    let tokens: Vec<&CssToken> = tokens
+        .iter()
+        .filter(|token| !matches!(token, CssToken::Whitespace | CssToken::Comment(_)))
+        .collect();
+    let mut pos = 0;
+
+    let expr = parse_calc_sum(&tokens, &mut pos, nesting)?;
+    match tokens.get(pos) {
+        Some(token) => Err(format!("unexpected '{token}' in calc()")),
+        None => Ok(expr),
+    }
+}
+
+fn parse_calc_sum(
+    tokens: &[&CssToken],
+    pos: &mut usize,
+    nesting: Nesting,
+) -> Result<CalcExpr, String> {
+    let mut expr = parse_calc_product(tokens, pos, nesting)?;
+    while let Some(CssToken::Delim(op @ ('+' | '-'))) = tokens.get(*pos) {
+        *pos += 1;
+        let right = Box::new(parse_calc_product(tokens, pos, nesting)?);
+        expr = match op {
+            '+' => CalcExpr::Add(Box::new(expr), right),
+            _ => CalcExpr::Sub(Box::new(expr), right),
+        };
+    }
+    Ok(expr)
+}
+
+fn parse_calc_product(
+    tokens: &[&CssToken],
+    pos: &mut usize,
+    nesting: Nesting,
+) -> Result<CalcExpr, String> {
+    let mut expr = parse_calc_term(tokens, pos, nesting)?;
+    while let Some(CssToken::Delim(op @ ('*' | '/'))) = tokens.get(*pos) {
+        *pos += 1;
+        let right = Box::new(parse_calc_term(tokens, pos, nesting)?);
+        expr = match op {
+            '*' => CalcExpr::Mul(Box::new(expr), right),
+            _ => CalcExpr::Div(Box::new(expr), right),
         };
+    }
+    Ok(expr)
+}
+
+fn parse_calc_term(
+    tokens: &[&CssToken],
+    pos: &mut usize,
+    nesting: Nesting,
+) -> Result<CalcExpr, String> {
+    let token = tokens.get(*pos).ok_or("unexpected end of calc()")?;
+    *pos += 1;
+    match token {
+        CssToken::Number(num, unit) => Ok(CalcExpr::Size(*num, parse_unit(unit)?)),
+        CssToken::OpenParen => parse_calc_group(tokens, pos, nesting.enter()?),
+        CssToken::Function(name) if name == "calc" => {
+            parse_calc_group(tokens, pos, nesting.enter()?)
+        }
+        token => Err(format!("unexpected '{token}' in calc()")),
+    }
+}
+
+fn parse_calc_group(
+    tokens: &[&CssToken],
+    pos: &mut usize,
+    nesting: Nesting,
+) -> Result<CalcExpr, String> {
+    let expr = parse_calc_sum(tokens, pos, nesting)?;
+    match tokens.get(*pos) {
+        Some(CssToken::CloseParen) => {
+            *pos += 1;
+            Ok(expr)
+        }
+        _ => Err("expected ')' in calc()".to_string()),
+    }
+}
+
+// Parses the inside of `var()`: a custom property name, optionally followed by
+// a comma and the fallback value.
+fn parse_var(tokens: &[CssToken], nesting: Nesting) -> Result<Value, String> {
+    let name = split_commas(tokens)[0];
+    let fallback = tokens.get(name.len() + 1..);
+    let name = match trim_whitespace(name) {
+        [CssToken::Ident(name)] if name.starts_with("--") => name.clone(),
+        _ => return Err("expected a custom property name in var()".to_string()),
+    };
+    let fallback = match fallback {
+        Some(fallback) => Some(Box::new(parse_value_tokens(fallback, nesting.enter()?)?)),
+        None => None,
+    };
+    Ok(Value::Var(name, fallback))
+}
+
+fn parse_single_value(tokens: &[CssToken], nesting: Nesting) -> Result<Value, String> {
+    match trim_whitespace(tokens) {
+        [CssToken::Function(name), inner @ .., CssToken::CloseParen] if name == "calc" => {
+            Ok(Value::Calc(parse_calc(inner, nesting)?))
+        }
+        [CssToken::Hash(hex)] => parse_color(hex),
+        [CssToken::Function(name), inner @ .., CssToken::CloseParen] if name == "var" => {
+            parse_var(inner, nesting)
+        }
+        [CssToken::Function(name), inner @ .., CssToken::CloseParen]
+            if ["rgb", "rgba", "hsl", "hsla"]
+                .iter()
+                .any(|function| name.eq_ignore_ascii_case(function)) =>
+        {
+            parse_color_function(name, inner)
+        }
+        [CssToken::Number(num, unit)] if unit.is_empty() => Ok(Value::Number(*num)),
+        [CssToken::Number(num, unit)] => Ok(Value::size(*num, parse_unit(unit)?)),
+        [CssToken::Ident(keyword)] => Ok(Value::keyword(keyword.clone())),
+        [CssToken::String(string)] => Ok(Value::String(string.clone())),
+        [CssToken::Url(url)] => Ok(Value::Url(url.clone())),
+        [CssToken::Function(name), inner @ .., CssToken::CloseParen]
+            if name.eq_ignore_ascii_case("url") =>
+        {
+            match trim_whitespace(inner) {
+                [CssToken::String(url)] => Ok(Value::Url(url.clone())),
+                _ => Err("invalid url()".to_string()),
+            }
+        }
+        [] => Err("missing value".to_string()),
+        tokens => Ok(Value::keyword(
+            tokens.iter().map(|token| token.to_string()).collect(),
+        )),
+    }
+}
+
+// Splits a trailing `!important` off a declaration's value, allowing
+// whitespace between the `!` and the keyword.
+fn strip_important(tokens: &[CssToken]) -> (&[CssToken], bool) {
+    if let [rest @ .., CssToken::Ident(keyword)] = trim_whitespace(tokens) {
+        if keyword.eq_ignore_ascii_case("important") {
+            if let [rest @ .., CssToken::Delim('!')] = trim_whitespace(rest) {
+                return (rest, true);
+            }
+        }
+    }
+    (tokens, false)
+}
+
+// Comma-separated values like `font-family: Arial, sans-serif` become a
+// CommaList. Empty items, e.g. from a trailing comma, are dropped.
+fn parse_value_tokens(tokens: &[CssToken], nesting: Nesting) -> Result<Value, String> {
+    let parts = split_commas(tokens);
+    if parts.len() == 1 {
+        return parse_single_value(parts[0], nesting);
+    }
+
+    Ok(Value::CommaList(
+        parts
+            .into_iter()
+            .map(trim_whitespace)
+            .filter(|part| !part.is_empty())
+            .map(|part| parse_single_value(part, nesting))
+            .collect::<Result<_, _>>()?,
+    ))
+}
+
+// Splits `font: [style] [weight] size[/line-height] family` into longhands.
+// Tokens it does not recognize, like `small-caps`, and the line height are skipped.
+fn expand_font_shorthand(
+    tokens: &[CssToken],
+    nesting: Nesting,
+) -> Result<Vec<Declaration>, String> {
+    let tokens = trim_whitespace(tokens);
+    let mut declarations = Vec::new();
 
-        return Value::size(num, unit);
+    let mut i = 0;
+    while i < tokens.len() {
+        match &tokens[i] {
+            CssToken::Ident(keyword) if keyword == "italic" || keyword == "oblique" => {
+                declarations.push(Declaration::new(
+                    "font-style".to_string(),
+                    Value::keyword(keyword.clone()),
+                ));
+            }
+            CssToken::Ident(keyword)
+                if matches!(keyword.as_str(), "bold" | "bolder" | "lighter") =>
+            {
+                declarations.push(Declaration::new(
+                    "font-weight".to_string(),
+                    Value::keyword(keyword.clone()),
+                ));
+            }
+            CssToken::Number(weight, unit) if unit.is_empty() => {
+                declarations.push(Declaration::new(
+                    "font-weight".to_string(),
+                    Value::Number(*weight),
+                ));
+            }
+            CssToken::Number(_, _) => break,
+            CssToken::Ident(keyword) if FONT_SIZE_KEYWORDS.contains(&keyword.as_str()) => break,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    if i == tokens.len() {
+        return Ok(declarations);
+    }
+    declarations.push(Declaration::new(
+        "font-size".to_string(),
+        parse_single_value(&tokens[i..=i], nesting)?,
+    ));
+
+    let mut rest = trim_whitespace(&tokens[i + 1..]);
+    if let [CssToken::Delim('/'), ..] = rest {
+        rest = trim_whitespace(&rest[1..]);
+        rest = rest.get(1..).unwrap_or_default();
+    }
+    if !trim_whitespace(rest).is_empty() {
+        declarations.push(Declaration::new(
+            "font-family".to_string(),
+            parse_value_tokens(rest, nesting)?,
+        ));
     }
 
-    Value::keyword(value)
+    Ok(declarations)
 }
 
 impl CSSParser {
     fn new(input: String) -> Self {
+        CSSParser::with_limits(input, ParserLimits::default())
+    }
+
+    fn with_limits(input: String, limits: ParserLimits) -> Self {
         CSSParser {
-            base: Parser::new(input),
+            tokens: tokenize_with_offsets(&input),
+            input,
+            pos: 0,
+            diagnostics: false,
+            warnings: Vec::new(),
+            limits,
         }
     }
 
-    fn is_valid_identifier_initial_char(&self) -> bool {
-        match self.base.next_char() {
-            'a'..='z' | 'A'..='Z' => true,
-            _ => false,
+    fn nesting(&self) -> Nesting {
+        Nesting::new(self.limits.max_depth)
+    }
+
+    // Warnings are only collected in diagnostics mode.
+    fn warn_at(&mut self, pos: usize, message: String) {
+        if self.diagnostics {
+            let message = self.error_at(pos, message).to_string();
+            self.warnings.push(Warning { message });
         }
     }
 
-    fn parse_identifier(&mut self) -> String {
-        self.base.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => true,
-            _ => false,
-        })
+    fn error_at(&self, pos: usize, message: String) -> CssParseError {
+        let offset = self
+            .tokens
+            .get(pos)
+            .map_or(self.input.len(), |(_, offset)| *offset);
+        let before = &self.input[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+
+        CssParseError {
+            message,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+
+    fn error(&self, message: String) -> CssParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn eof(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&CssToken> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn expect(&mut self, expected: CssToken) -> ParseResult<()> {
+        match self.peek() {
+            Some(token) if *token == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => Err(self.error(format!("expected '{expected}', found '{token}'"))),
+            None => Err(self.error(format!("expected '{expected}', found end of input"))),
+        }
+    }
+
+    fn consume_whitespace(&mut self) {
+        while let Some(CssToken::Whitespace | CssToken::Comment(_)) = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn parse_identifier(&mut self) -> ParseResult<String> {
+        match self.peek() {
+            Some(CssToken::Ident(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            Some(token) => Err(self.error(format!("expected an identifier, found '{token}'"))),
+            None => Err(self.error("expected an identifier, found end of input".to_string())),
+        }
+    }
+
+    fn is_compound_start(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                CssToken::Ident(_)
+                    | CssToken::Hash(_)
+                    | CssToken::Delim('.' | '*')
+                    | CssToken::Colon
+            )
+        )
+    }
+
+    fn parse_pseudo_class(&mut self) -> ParseResult<PseudoClass> {
+        self.expect(CssToken::Colon)?;
+        match self.peek() {
+            Some(CssToken::Function(name))
+                if name.eq_ignore_ascii_case("nth-child")
+                    || name.eq_ignore_ascii_case("nth-of-type") =>
+            {
+                let function = name.to_ascii_lowercase();
+                // The argument is read from the source, as the tokenizer splits
+                // `2n+1` into a dimension and a signed number.
+                let function_offset = self.tokens[self.pos].1;
+                let text_start =
+                    function_offset + self.input[function_offset..].find('(').unwrap() + 1;
+                self.pos += 1;
+                let start = self.pos;
+                while !matches!(self.peek(), Some(CssToken::CloseParen) | None) {
+                    self.pos += 1;
+                }
+                if self.eof() {
+                    return Err(self.error(format!("unterminated :{function}()")));
+                }
+                let text = &self.input[text_start..self.tokens[self.pos].1];
+                let formula = parse_nth_formula(&function, text)
+                    .map_err(|message| self.error_at(start, message))?;
+                self.pos += 1;
+                Ok(match function.as_str() {
+                    "nth-child" => PseudoClass::NthChild(formula),
+                    _ => PseudoClass::NthOfType(formula),
+                })
+            }
+            Some(CssToken::Function(name)) if name.eq_ignore_ascii_case("lang") => {
+                self.pos += 1;
+                self.consume_whitespace();
+                let language = match self.peek() {
+                    Some(CssToken::Ident(language) | CssToken::String(language)) => {
+                        language.clone()
+                    }
+                    Some(token) => {
+                        return Err(self.error(format!("expected a language, found '{token}'")))
+                    }
+                    None => {
+                        return Err(
+                            self.error("expected a language, found end of input".to_string())
+                        )
+                    }
+                };
+                self.pos += 1;
+                self.consume_whitespace();
+                self.expect(CssToken::CloseParen)?;
+                Ok(PseudoClass::Lang(language))
+            }
+            Some(CssToken::Ident(name)) => {
+                let pseudo_class = match name.to_ascii_lowercase().as_str() {
+                    "root" => PseudoClass::Root,
+                    "first-of-type" => PseudoClass::FirstOfType,
+                    "last-of-type" => PseudoClass::LastOfType,
+                    _ => return Err(self.error(format!("unknown pseudo-class ':{name}'"))),
+                };
+                self.pos += 1;
+                Ok(pseudo_class)
+            }
+            Some(CssToken::Function(name)) => {
+                Err(self.error(format!("unknown pseudo-class ':{name}'")))
+            }
+            Some(token) => Err(self.error(format!("expected a pseudo-class, found '{token}'"))),
+            None => Err(self.error("expected a pseudo-class, found end of input".to_string())),
+        }
+    }
+
+    fn parse_pseudo_element(&mut self) -> ParseResult<PseudoElement> {
+        self.expect(CssToken::Colon)?;
+        self.expect(CssToken::Colon)?;
+        let pseudo_element = match self.peek() {
+            Some(CssToken::Ident(name)) => match name.to_ascii_lowercase().as_str() {
+                "before" => PseudoElement::Before,
+                "after" => PseudoElement::After,
+                _ => return Err(self.error(format!("unknown pseudo-element '::{name}'"))),
+            },
+            Some(token) => {
+                return Err(self.error(format!("expected a pseudo-element, found '{token}'")))
+            }
+            None => {
+                return Err(self.error("expected a pseudo-element, found end of input".to_string()))
+            }
+        };
+        self.pos += 1;
+        Ok(pseudo_element)
     }
 
-    fn parse_selector(&mut self) -> Selector {
+    fn parse_compound_selector(&mut self) -> ParseResult<Selector> {
         let mut selector = Selector::new(None, None, Vec::new());
 
-        while !self.base.eof() {
-            self.base.consume_whitespace();
-            match self.base.next_char() {
-                '#' => {
-                    self.base.consume_char();
-                    selector.id = Some(self.parse_identifier());
+        loop {
+            if selector.pseudo_element.is_some() && self.is_compound_start() {
+                return Err(self.error("a pseudo-element must come last in a selector".to_string()));
+            }
+            match self.peek() {
+                Some(CssToken::Hash(id)) => {
+                    // `#123` is fine as a color but an id, like any identifier,
+                    // cannot start with an unescaped digit.
+                    let offset = self.tokens[self.pos].1;
+                    if self.input[offset + 1..].starts_with(|c: char| c.is_ascii_digit()) {
+                        return Err(
+                            self.error(format!("an id must not start with a digit, found '#{id}'"))
+                        );
+                    }
+                    selector.id = Some(id.clone());
+                    self.pos += 1;
+                }
+                Some(CssToken::Delim('.')) => {
+                    self.pos += 1;
+                    selector.class.push(self.parse_identifier()?);
+                }
+                Some(CssToken::Colon)
+                    if self
+                        .tokens
+                        .get(self.pos + 1)
+                        .is_some_and(|(token, _)| *token == CssToken::Colon) =>
+                {
+                    selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                }
+                Some(CssToken::Colon) => {
+                    let pseudo_class = self.parse_pseudo_class()?;
+                    selector.pseudo_classes.push(pseudo_class);
                 }
-                '.' => {
-                    self.base.consume_char();
-                    selector.class.push(self.parse_identifier());
+                Some(CssToken::Ident(tag)) => {
+                    if selector != Selector::new(None, None, Vec::new()) {
+                        return Err(self.error(format!(
+                            "a tag name must come first in a compound selector, found '{tag}'"
+                        )));
+                    }
+                    selector.tag = Some(tag.to_ascii_lowercase());
+                    self.pos += 1;
                 }
-                _ if self.is_valid_identifier_initial_char() => {
-                    selector.tag = Some(self.parse_identifier());
+                Some(CssToken::Delim('*')) => {
+                    if selector != Selector::new(None, None, Vec::new()) {
+                        return Err(
+                            self.error("'*' must come first in a compound selector".to_string())
+                        );
+                    }
+                    selector.universal = true;
+                    self.pos += 1;
+                }
+                Some(CssToken::Comment(_)) => {
+                    self.pos += 1;
                 }
                 _ => {
                     break;
@@ -85,87 +790,449 @@ impl CSSParser {
             }
         }
 
-        selector
+        // Matching every element takes an explicit `*`, so that a stray comma
+        // or a missing selector is not read as one.
+        if selector == Selector::new(None, None, Vec::new()) {
+            return Err(match self.peek() {
+                Some(token) => self.error(format!("expected a selector, found '{token}'")),
+                None => self.error("expected a selector, found end of input".to_string()),
+            });
+        }
+        Ok(selector)
+    }
+
+    fn parse_selector(&mut self) -> ParseResult<Selector> {
+        let mut selector = self.parse_compound_selector()?;
+
+        loop {
+            let start = self.pos;
+            self.consume_whitespace();
+            let explicit = match self.peek() {
+                Some(CssToken::Delim('>')) => Some(Combinator::Child),
+                Some(CssToken::Delim('+')) => Some(Combinator::NextSibling),
+                Some(CssToken::Delim('~')) => Some(Combinator::SubsequentSibling),
+                _ => None,
+            };
+            let combinator = if let Some(combinator) = explicit {
+                self.pos += 1;
+                self.consume_whitespace();
+                combinator
+            } else if self.pos > start && self.is_compound_start() {
+                Combinator::Descendant
+            } else {
+                self.pos = start;
+                break;
+            };
+            if selector.pseudo_element.is_some() {
+                return Err(self.error("a pseudo-element must come last in a selector".to_string()));
+            }
+            selector = self
+                .parse_compound_selector()?
+                .with_combinator(combinator, selector);
+        }
+
+        Ok(selector)
     }
 
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> ParseResult<Vec<Selector>> {
         let mut selectors = Vec::new();
 
-        while !self.base.eof() {
-            self.base.consume_whitespace();
+        while !self.eof() {
+            self.consume_whitespace();
 
-            selectors.push(self.parse_selector());
+            selectors.push(self.parse_selector()?);
 
-            self.base.consume_whitespace();
-            if self.base.eof() || self.base.next_char() != ',' {
+            self.consume_whitespace();
+            if self.peek() != Some(&CssToken::Comma) {
                 break;
             }
-            self.base.consume_char();
+            self.pos += 1;
+        }
+
+        Ok(selectors)
+    }
+
+    // Reads up to the `;` or `}` that ends the value, outside any brackets. A
+    // stray `)` or `]` is kept as a token, so it cannot hide that end.
+    fn parse_value_tokens(&mut self) -> Vec<CssToken> {
+        let mut tokens = Vec::new();
+        let mut depth: usize = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                CssToken::Semicolon | CssToken::CloseBrace if depth == 0 => break,
+                CssToken::Function(_) | CssToken::OpenParen | CssToken::OpenBracket => depth += 1,
+                CssToken::CloseParen | CssToken::CloseBracket => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            tokens.push(token.clone());
+            self.pos += 1;
+        }
+
+        tokens
+    }
+
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        self.consume_whitespace();
+        let start = self.pos;
+        let tokens = self.parse_value_tokens();
+        parse_value_tokens(&tokens, self.nesting()).map_err(|message| self.error_at(start, message))
+    }
+
+    fn parse_named_declaration(&mut self) -> ParseResult<Vec<Declaration>> {
+        let start = self.pos;
+        let name = self.parse_identifier()?;
+
+        self.consume_whitespace();
+        self.expect(CssToken::Colon)?;
+
+        if !name.starts_with("--") && !KNOWN_PROPERTIES.contains(&name.as_str()) {
+            self.warn_at(start, format!("unknown property '{name}'"));
         }
+        self.parse_declaration(name)
+    }
+
+    // Parses everything after the colon of one declaration. Custom properties
+    // accept any tokens, so their value is kept as the text written in the
+    // source, and `font` expands into its longhands.
+    fn parse_declaration(&mut self, name: String) -> ParseResult<Vec<Declaration>> {
+        self.consume_whitespace();
+        let start = self.pos;
+        let tokens = self.parse_value_tokens();
+        let (tokens, important) = strip_important(&tokens);
 
-        selectors
+        let mut declarations = if name.starts_with("--") {
+            let offset = |index: usize| {
+                self.tokens
+                    .get(index)
+                    .map_or(self.input.len(), |(_, offset)| *offset)
+            };
+            let text = &self.input[offset(start)..offset(start + tokens.len())];
+            vec![Declaration::new(
+                name,
+                Value::Raw(text.trim_end().to_string()),
+            )]
+        } else if name == "font" {
+            expand_font_shorthand(tokens, self.nesting())
+                .map_err(|message| self.error_at(start, message))?
+        } else {
+            let value = parse_value_tokens(tokens, self.nesting())
+                .map_err(|message| self.error_at(start, message))?;
+            vec![Declaration::new(name, value)]
+        };
+        for declaration in &mut declarations {
+            declaration.important = important;
+        }
+        Ok(declarations)
     }
 
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert!(self.base.consume_char() == '{');
+    fn parse_declarations(&mut self) -> ParseResult<Vec<Declaration>> {
+        self.expect(CssToken::OpenBrace)?;
+        self.parse_declaration_list(false)
+    }
 
+    // Reads declarations up to the closing `}`, which is consumed. A `style`
+    // attribute has no braces, so there the list ends with the input instead.
+    fn parse_declaration_list(&mut self, inline: bool) -> ParseResult<Vec<Declaration>> {
         let mut declarations = Vec::new();
 
         loop {
-            self.base.consume_whitespace();
+            self.consume_whitespace();
 
-            if self.base.next_char() == '}' {
-                self.base.consume_char();
-                break;
+            match self.peek() {
+                Some(CssToken::CloseBrace) if !inline => {
+                    self.pos += 1;
+                    break;
+                }
+                None if inline => break,
+                _ => {}
+            }
+
+            // In diagnostics mode a malformed declaration is dropped up to the
+            // next `;` or `}` instead of failing the whole stylesheet.
+            let start = self.pos;
+            match self.parse_named_declaration() {
+                Ok(parsed) => declarations.extend(parsed),
+                Err(error) if self.diagnostics && !self.eof() => {
+                    self.warn_at(
+                        start,
+                        format!("dropped malformed declaration ({})", error.message),
+                    );
+                    self.pos = start;
+                    self.parse_value_tokens();
+                }
+                Err(error) => return Err(error),
+            }
+            if self.peek() == Some(&CssToken::Semicolon) {
+                self.pos += 1;
             }
+        }
+
+        Ok(declarations)
+    }
+
+    fn parse_rule(&mut self) -> ParseResult<Rule> {
+        self.consume_whitespace();
+        let selectors = self.parse_selectors()?;
+
+        self.consume_whitespace();
+        let declarations = self.parse_declarations()?;
+
+        Ok(Rule::new(selectors, declarations))
+    }
+
+    // Skips an at-rule this parser does not model, either a statement like
+    // `@import "a.css";` or a block like `@font-face { ... }`, nested blocks included.
+    fn skip_at_rule(&mut self) -> ParseResult<()> {
+        let start = self.pos;
+        let mut depth = 0;
 
-            let name = self.parse_identifier();
+        while let Some(token) = self.peek().cloned() {
+            self.pos += 1;
+            match token {
+                CssToken::Semicolon if depth == 0 => return Ok(()),
+                CssToken::Function(_)
+                | CssToken::OpenParen
+                | CssToken::OpenBracket
+                | CssToken::OpenBrace => depth += 1,
+                CssToken::CloseParen | CssToken::CloseBracket => depth -= 1,
+                CssToken::CloseBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
 
-            self.base.consume_whitespace();
-            assert!(self.base.consume_char() == ':');
-            self.base.consume_whitespace();
+        Err(self.error_at(start, "unterminated at-rule".to_string()))
+    }
 
-            let valueText = self.base.consume_while(|c| c != ';');
-            assert!(self.base.consume_char() == ';');
+    // Reads what is inside the parentheses of a feature like `(min-width: 600px)`.
+    fn parse_media_feature(&mut self) -> Option<MediaFeature> {
+        self.consume_whitespace();
+        let Some(CssToken::Ident(name)) = self.peek().cloned() else {
+            return None;
+        };
+        self.pos += 1;
+        self.consume_whitespace();
+        if self.peek() != Some(&CssToken::Colon) {
+            return None;
+        }
+        self.pos += 1;
+        self.consume_whitespace();
+        let value = self.peek()?.clone();
+        self.pos += 1;
+        self.consume_whitespace();
+        if self.peek() != Some(&CssToken::CloseParen) {
+            return None;
+        }
+        self.pos += 1;
 
-            declarations.push(Declaration::new(name, parse_value(valueText)));
+        let width = |x: f32, unit: &str| match unit.to_ascii_lowercase().as_str() {
+            "px" => Some(x),
+            "em" | "rem" => Some(x * DEFAULT_FONT_SIZE),
+            "" if x == 0.0 => Some(0.0),
+            _ => None,
+        };
+        let keyword = match value {
+            CssToken::Ident(ref keyword) => keyword.to_ascii_lowercase(),
+            _ => String::new(),
+        };
+        match (name.to_ascii_lowercase().as_str(), &value, keyword.as_str()) {
+            ("min-width", CssToken::Number(x, unit), _) => {
+                width(*x, unit).map(MediaFeature::MinWidth)
+            }
+            ("max-width", CssToken::Number(x, unit), _) => {
+                width(*x, unit).map(MediaFeature::MaxWidth)
+            }
+            ("prefers-color-scheme", _, "light") => {
+                Some(MediaFeature::PrefersColorScheme(ColorScheme::Light))
+            }
+            ("prefers-color-scheme", _, "dark") => {
+                Some(MediaFeature::PrefersColorScheme(ColorScheme::Dark))
+            }
+            ("orientation", _, "landscape") => {
+                Some(MediaFeature::Orientation(Orientation::Landscape))
+            }
+            ("orientation", _, "portrait") => {
+                Some(MediaFeature::Orientation(Orientation::Portrait))
+            }
+            _ => None,
         }
+    }
 
-        declarations
+    // Reads a query like `screen and (min-width: 600px) and (orientation:
+    // portrait)`, up to the `{`. Returns None for anything else, such as other
+    // media types, query lists or unknown features.
+    fn parse_media_query(&mut self) -> Option<MediaQuery> {
+        let mut features = Vec::new();
+        let mut expect_term = true;
+        let mut is_first = true;
+
+        loop {
+            self.consume_whitespace();
+            match self.peek()?.clone() {
+                CssToken::OpenBrace if !expect_term => return Some(MediaQuery::new(features)),
+                CssToken::Ident(word) if is_first => match word.to_ascii_lowercase().as_str() {
+                    "only" => {
+                        self.pos += 1;
+                        continue;
+                    }
+                    "all" | "screen" => self.pos += 1,
+                    _ => return None,
+                },
+                CssToken::OpenParen if expect_term => {
+                    self.pos += 1;
+                    features.push(self.parse_media_feature()?);
+                }
+                CssToken::Ident(word) if !expect_term && word.eq_ignore_ascii_case("and") => {
+                    self.pos += 1;
+                    expect_term = true;
+                    continue;
+                }
+                _ => return None,
+            }
+            expect_term = false;
+            is_first = false;
+        }
     }
 
-    fn parse_rule(&mut self) -> Rule {
-        self.base.consume_whitespace();
-        let selectors = self.parse_selectors();
+    // An `@media` block whose query is not understood is skipped like any
+    // other at-rule, so its rules never apply.
+    fn parse_media_rule(&mut self) -> ParseResult<Vec<Rule>> {
+        let start = self.pos;
+        self.pos += 1;
+        let Some(media) = self.parse_media_query() else {
+            self.pos = start;
+            self.skip_at_rule()?;
+            return Ok(Vec::new());
+        };
+        self.expect(CssToken::OpenBrace)?;
 
-        self.base.consume_whitespace();
-        let declarations = self.parse_declarations();
+        let mut rules = Vec::new();
+        loop {
+            self.consume_whitespace();
 
-        Rule::new(selectors, declarations)
+            match self.peek() {
+                None => return Err(self.error_at(start, "unterminated at-rule".to_string())),
+                Some(CssToken::CloseBrace) => {
+                    self.pos += 1;
+                    return Ok(rules);
+                }
+                Some(CssToken::AtKeyword(_)) => self.skip_at_rule()?,
+                Some(_) => rules.push(self.parse_rule()?.with_media(media.clone())),
+            }
+        }
     }
 
-    fn parse_stylesheet(&mut self) -> StyleSheet {
+    fn parse_stylesheet(&mut self) -> ParseResult<StyleSheet> {
         let mut rules = Vec::new();
 
         loop {
-            self.base.consume_whitespace();
+            self.consume_whitespace();
 
-            if self.base.eof() {
-                break;
+            match self.peek() {
+                None => break,
+                Some(CssToken::AtKeyword(name)) if name.eq_ignore_ascii_case("media") => {
+                    rules.extend(self.parse_media_rule()?)
+                }
+                Some(CssToken::AtKeyword(_)) => self.skip_at_rule()?,
+                Some(_) => rules.push(self.parse_rule()?),
             }
+        }
+
+        Ok(StyleSheet::new(rules))
+    }
+}
 
-            rules.push(self.parse_rule());
+// Parses one selector, like `ul > li.active`. A selector list is rejected.
+pub fn parse_selector(data: &str) -> Result<Selector, CssParseError> {
+    let mut parser = CSSParser::new(data.to_string());
+    parser.consume_whitespace();
+    if !parser.is_compound_start() {
+        return Err(parser.error("expected a selector".to_string()));
+    }
+
+    let selector = parser.parse_selector()?;
+    parser.consume_whitespace();
+    match parser.peek() {
+        Some(CssToken::Comma) => {
+            Err(parser.error("expected a single selector, found ','".to_string()))
         }
+        Some(token) => Err(parser.error(format!("unexpected '{token}' after selector"))),
+        None => Ok(selector),
+    }
+}
 
-        StyleSheet::new(rules)
+/// Parses a value the way it is read on the right-hand side of a declaration,
+/// including `calc()`, `url()` and comma-separated lists.
+///
+/// ```
+/// use bruser::css::parse_value;
+/// use bruser::style::{Unit, Value};
+///
+/// assert_eq!(parse_value("#ff8000").unwrap(), Value::color(255, 128, 0));
+/// assert_eq!(parse_value("1.5em").unwrap(), Value::size(1.5, Unit::Em));
+/// assert_eq!(parse_value("block").unwrap(), Value::keyword("block".to_string()));
+/// assert!(parse_value("red; color: blue").is_err());
+/// ```
+pub fn parse_value(data: &str) -> Result<Value, CssParseError> {
+    let mut parser = CSSParser::new(data.to_string());
+    let value = parser.parse_value()?;
+    match parser.peek() {
+        Some(token) => Err(parser.error(format!("unexpected '{token}' after value"))),
+        None => Ok(value),
     }
 }
 
-pub fn parse(data: String) -> StyleSheet {
-    let mut parser = CSSParser::new(data);
+// Parses the declarations of a `style` attribute, like `color: red; margin: 0`.
+pub fn parse_inline_style(data: &str) -> Result<Vec<Declaration>, CssParseError> {
+    let mut parser = CSSParser::new(data.to_string());
+    parser.parse_declaration_list(true)
+}
+
+// `max_depth` bounds how deeply a value nests, through `calc()` parentheses
+// and `var()` fallbacks. `max_attributes` is for HTML only.
+pub fn parse_with_limits(data: String, limits: ParserLimits) -> Result<StyleSheet, CssParseError> {
+    if let Err(message) = limits.check_input_size(&data) {
+        return Err(CssParseError {
+            message,
+            line: 1,
+            column: 1,
+        });
+    }
+
+    let mut parser = CSSParser::with_limits(data, limits);
     parser.parse_stylesheet()
 }
 
+// Like `parse`, but a malformed declaration is dropped with a warning instead
+// of failing, and unknown properties are reported too. Warnings found before a
+// fatal error are still returned.
+pub fn parse_with_diagnostics(data: String) -> (Result<StyleSheet, CssParseError>, Vec<Warning>) {
+    if let Err(message) = ParserLimits::default().check_input_size(&data) {
+        return (
+            Err(CssParseError {
+                message,
+                line: 1,
+                column: 1,
+            }),
+            Vec::new(),
+        );
+    }
+
+    let mut parser = CSSParser::new(data);
+    parser.diagnostics = true;
+    let result = parser.parse_stylesheet();
+    (result, parser.warnings)
+}
+
+pub fn parse(data: String) -> Result<StyleSheet, CssParseError> {
+    parse_with_limits(data, ParserLimits::default())
+}
+
 #[cfg(test)]
 mod tests {
     extern crate rstest;
@@ -175,6 +1242,7 @@ mod tests {
     use speculate::speculate;
 
     use super::*;
+    use crate::style::{LengthContext, Viewport};
 
     speculate! {
         describe "'parse_value'" {
@@ -185,17 +1253,16 @@ mod tests {
                     case("#abcdef", Value::color(171, 205, 239)),
                 )]
                 fn parse_color_code(input: &str, expected: Value) {
-                    assert_eq!(parse_value(input.to_string()), expected);
+                    assert_eq!(parse_value(input).unwrap(), expected);
                 }
 
-                #[should_panic]
-                #[rstest(input,
-                    case("#123"),
-                    case("#1111111"),
-                    case("#zyxwvut"),
+                #[rstest(input, expected,
+                    case("#123", "invalid color '#123' at 1:1"),
+                    case("#1111111", "invalid color '#1111111' at 1:1"),
+                    case("#zyxwvut", "invalid color '#zyxwvut' at 1:1"),
                 )]
-                fn fail_to_parse_with_invalid_color(input: &str) {
-                    parse_value(input.to_string());
+                fn fail_to_parse_with_invalid_color(input: &str, expected: &str) {
+                    assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
                 }
             }
 
@@ -203,32 +1270,186 @@ mod tests {
                 #[rstest(input, expected,
                     case("10px", Value::size(10.0, Unit::Px)),
                     case("43%", Value::size(43.0, Unit::Percent)),
+                    case("43.5%", Value::size(43.5, Unit::Percent)),
+                    case(".5%", Value::size(0.5, Unit::Percent)),
+                    case("-5%", Value::size(-5.0, Unit::Percent)),
+                    case("-1.5em", Value::size(-1.5, Unit::Em)),
                     case("1.4em", Value::size(1.4, Unit::Em)),
                     case("0.1rem", Value::size(0.1, Unit::Rem)),
-                    case("10000", Value::size(10000.0, Unit::None)),
+                    case("50vw", Value::size(50.0, Unit::Vw)),
+                    case("100vh", Value::size(100.0, Unit::Vh)),
+                    case("10000", Value::Number(10000.0)),
                 )]
                 fn parse_color_code(input: &str, expected: Value) {
-                    assert_eq!(parse_value(input.to_string()), expected);
+                    assert_eq!(parse_value(input).unwrap(), expected);
                 }
 
-                #[should_panic]
-                #[rstest(input,
-                    case("1hogehogepx"),
-                    case("1ab"),
+                #[rstest(input, expected,
+                    case("1hogehogepx", "unknown unit 'hogehogepx' at 1:1"),
+                    case("1ab", "unknown unit 'ab' at 1:1"),
                 )]
-                fn fail_to_parse_with_invalid_size(input: &str) {
-                    parse_value(input.to_string());
+                fn fail_to_parse_with_invalid_size(input: &str, expected: &str) {
+                    assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
                 }
             }
         }
 
-        describe "'parse_selectors' parse selector" {
+        describe "if value is a color function, value is parsed to color" {
             #[rstest(input, expected,
-                case(
-                    ".hoge__fizz-bar",
-                    Vec::from([Selector::new(None, None, Vec::from(["hoge__fizz-bar".to_string()]))])
-                ),
-                case(
+                case("rgb(255, 128, 0)", Value::color(255, 128, 0)),
+                case("rgb(100%,0%,0%)", Value::color(255, 0, 0)),
+                case("rgb(50%, 0%, 100%)", Value::color(128, 0, 255)),
+                case("RGB(300, -20, 12.6)", Value::color(255, 0, 13)),
+                case("rgb(0 128 255)", Value::color(0, 128, 255)),
+                case("rgba(0, 0, 0, 0.5)", Value::Color(Color::rgba(0, 0, 0, 128))),
+                case("rgb(0 0 0 / 25%)", Value::Color(Color::rgba(0, 0, 0, 64))),
+                case("hsl(0, 100%, 50%)", Value::color(255, 0, 0)),
+                case("hsl(120, 100%, 25%)", Value::color(0, 128, 0)),
+                case("hsl(240deg, 100%, 50%)", Value::color(0, 0, 255)),
+                case("hsl(-120, 100%, 50%)", Value::color(0, 0, 255)),
+                case("hsl(60, 100%, 50%)", Value::color(255, 255, 0)),
+                case("hsl(0, 0%, 100%)", Value::color(255, 255, 255)),
+                case("hsl(210, 50%, 40%)", Value::color(51, 102, 153)),
+                case("hsla(0, 100%, 50%, 0)", Value::Color(Color::rgba(255, 0, 0, 0))),
+            )]
+            fn test_parse_color_function(input: &str, expected: Value) {
+                assert_eq!(parse_value(input).unwrap(), expected);
+            }
+
+            #[rstest]
+            fn test_equals_hex_color() {
+                assert_eq!(parse_value("rgb(100%,0%,0%)").unwrap(), parse_value("#ff0000").unwrap());
+                assert_eq!(parse_value("hsl(0,100%,50%)").unwrap(), parse_value("#ff0000").unwrap());
+            }
+
+            #[rstest(input, expected,
+                case("rgb(1, 2)", "expected 3 or 4 arguments in rgb() at 1:1"),
+                case("rgba(1, 2, 3, 4, 5)", "expected 3 or 4 arguments in rgba() at 1:1"),
+                case("rgb(1px, 2, 3)", "unexpected '1px' in rgb() at 1:1"),
+                case("rgb(red, 2, 3)", "unexpected 'red' in rgb() at 1:1"),
+                case("hsl(0, 100, 50%)", "unexpected '100' in hsl() at 1:1"),
+            )]
+            fn test_fail_with_invalid_color_function(input: &str, expected: &str) {
+                assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "if value is 'calc()', value is parsed to an expression" {
+            #[rstest(input, expected,
+                case("calc(100% - 20px)", 380.0),
+                case("calc(10px + 2 * 5px)", 20.0),
+                case("calc((10px + 2) * 5)", 60.0),
+                case("calc(100% / 4 - calc(1em + 2px))", 78.0),
+                case("calc(1rem)", 16.0),
+            )]
+            fn parse_calc(input: &str, expected: f32) {
+                let context = LengthContext { font_size: 20.0, root_font_size: 16.0, percent_base: 400.0, viewport: Viewport { width: 1000.0, height: 500.0 } };
+                let value = parse_value(input).unwrap();
+
+                assert_eq!(value.to_px(&context), Some(expected));
+            }
+
+            #[rstest]
+            fn parse_calc_tree() {
+                assert_eq!(
+                    parse_value("calc(100% - 20px)").unwrap(),
+                    Value::Calc(CalcExpr::Sub(
+                        Box::new(CalcExpr::Size(100.0, Unit::Percent)),
+                        Box::new(CalcExpr::Size(20.0, Unit::Px)),
+                    ))
+                );
+            }
+
+            #[rstest(input, expected,
+                case("calc(10px +)", "unexpected end of calc() at 1:1"),
+                case("calc(10px 20px)", "unexpected '20px' in calc() at 1:1"),
+                case("calc((10px)", "expected ')' in calc() at 1:1"),
+                case("calc(auto)", "unexpected 'auto' in calc() at 1:1"),
+                case("calc(100%-20px)", "unexpected '-20px' in calc() at 1:1"),
+            )]
+            fn fail_to_parse_invalid_calc(input: &str, expected: &str) {
+                assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "if value is 'url()', value is parsed to url" {
+            #[rstest(input, expected,
+                case("url(\"a.png\")", "a.png"),
+                case("url( 'img/a b.png' )", "img/a b.png"),
+                case("url(a.png)", "a.png"),
+                case("url(  https://example.com/a.png?x=1&y=2 )", "https://example.com/a.png?x=1&y=2"),
+                case("url(data:image/png;base64,iVBORw0KGgo=)", "data:image/png;base64,iVBORw0KGgo="),
+            )]
+            fn parse_url(input: &str, expected: &str) {
+                assert_eq!(parse_value(input).unwrap(), Value::Url(expected.to_string()));
+            }
+
+            #[rstest]
+            fn parse_background_image() {
+                let mut css_parser = CSSParser::new("{ background-image: url(\"a.png\"); }".to_string());
+
+                assert_eq!(
+                    css_parser.parse_declarations().unwrap(),
+                    Vec::from([Declaration::new("background-image".to_string(), Value::Url("a.png".to_string()))])
+                );
+            }
+
+            #[rstest]
+            fn fail_to_parse_invalid_url() {
+                assert_eq!(parse_value("url(\"a\" \"b\")").unwrap_err().to_string(), "invalid url() at 1:1");
+            }
+        }
+
+        describe "if value contains commas, value is parsed to comma list" {
+            #[rstest(input, expected,
+                case(
+                    "Arial, \"Helvetica Neue\", sans-serif",
+                    Value::CommaList(Vec::from([
+                        Value::keyword("Arial".to_string()),
+                        Value::String("Helvetica Neue".to_string()),
+                        Value::keyword("sans-serif".to_string()),
+                    ]))
+                ),
+                case(
+                    "Arial, sans-serif,",
+                    Value::CommaList(Vec::from([
+                        Value::keyword("Arial".to_string()),
+                        Value::keyword("sans-serif".to_string()),
+                    ]))
+                ),
+                case(
+                    "10px, 1.5em, 50%, 0.5rem, 2",
+                    Value::CommaList(Vec::from([
+                        Value::size(10.0, Unit::Px),
+                        Value::size(1.5, Unit::Em),
+                        Value::size(50.0, Unit::Percent),
+                        Value::size(0.5, Unit::Rem),
+                        Value::Number(2.0),
+                    ]))
+                ),
+                case("\"Helvetica Neue\"", Value::String("Helvetica Neue".to_string())),
+            )]
+            fn parse_comma_list(input: &str, expected: Value) {
+                assert_eq!(parse_value(input).unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case("10px, 1foo", "unknown unit 'foo' at 1:1"),
+                case("red; color: blue", "unexpected ';' after value at 1:4"),
+                case("red }", "unexpected '}' after value at 1:5"),
+            )]
+            fn fail_with_invalid_list_or_trailing_tokens(input: &str, expected: &str) {
+                assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selectors' parse selector" {
+            #[rstest(input, expected,
+                case(
+                    ".hoge__fizz-bar",
+                    Vec::from([Selector::new(None, None, Vec::from(["hoge__fizz-bar".to_string()]))])
+                ),
+                case(
                     "div.a.b.c.d",
                     Vec::from([Selector::new(Some("div".to_string()), None,  Vec::from(["a".to_string(), "b".to_string(), "c".to_string(), "d".to_string() ]))]),
                 ),
@@ -256,7 +1477,281 @@ mod tests {
             fn test_parse_tag_id_class(input: &str, expected: Vec::<Selector>) {
                 let mut css_parser = CSSParser::new(input.to_string());
 
-                assert_eq!(css_parser.parse_selectors(), expected);
+                assert_eq!(css_parser.parse_selectors().unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case("#\\31 0", Selector::new(None, Some("10".to_string()), Vec::new())),
+                case(".\\31 foo", Selector::new(None, None, Vec::from(["1foo".to_string()]))),
+                case(".data-1", Selector::new(None, None, Vec::from(["data-1".to_string()]))),
+                case("#a\\.b", Selector::new(None, Some("a.b".to_string()), Vec::new())),
+            )]
+            fn test_parse_escaped_identifier(input: &str, expected: Selector) {
+                assert_eq!(parse_selector(input).unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case(".1foo", "expected a selector at 1:1"),
+                case("div.1foo", "unexpected '0.1foo' after selector at 1:4"),
+                case("#1foo", "an id must not start with a digit, found '#1foo' at 1:1"),
+                case("div#2", "an id must not start with a digit, found '#2' at 1:4"),
+            )]
+            fn test_fail_with_leading_digit(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' reads compounds separated by whitespace as descendant" {
+            #[rstest(input, expected,
+                case("divspan", Selector::new(Some("divspan".to_string()), None, Vec::new())),
+                case(
+                    "div span",
+                    Selector::new(Some("span".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Descendant, Selector::new(Some("div".to_string()), None, Vec::new()))
+                ),
+                case("div.a.b", Selector::new(Some("div".to_string()), None, Vec::from(["a".to_string(), "b".to_string()]))),
+                case(
+                    "div.a#x.b div2",
+                    Selector::new(Some("div2".to_string()), None, Vec::new())
+                        .with_combinator(
+                            Combinator::Descendant,
+                            Selector::new(Some("div".to_string()), Some("x".to_string()), Vec::from(["a".to_string(), "b".to_string()]))
+                        )
+                ),
+                case(
+                    "ul > li",
+                    Selector::new(Some("li".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Child, Selector::new(Some("ul".to_string()), None, Vec::new()))
+                ),
+                case(
+                    "ul>li",
+                    Selector::new(Some("li".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Child, Selector::new(Some("ul".to_string()), None, Vec::new()))
+                ),
+                case(
+                    "h1 + p",
+                    Selector::new(Some("p".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::NextSibling, Selector::new(Some("h1".to_string()), None, Vec::new()))
+                ),
+                case(
+                    "h1~p",
+                    Selector::new(Some("p".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::SubsequentSibling, Selector::new(Some("h1".to_string()), None, Vec::new()))
+                ),
+                case(
+                    "div > h1 + .item",
+                    Selector::new(None, None, Vec::from(["item".to_string()]))
+                        .with_combinator(
+                            Combinator::NextSibling,
+                            Selector::new(Some("h1".to_string()), None, Vec::new())
+                                .with_combinator(Combinator::Child, Selector::new(Some("div".to_string()), None, Vec::new()))
+                        )
+                ),
+                case(
+                    "ul  li .item",
+                    Selector::new(None, None, Vec::from(["item".to_string()]))
+                        .with_combinator(
+                            Combinator::Descendant,
+                            Selector::new(Some("li".to_string()), None, Vec::new())
+                                .with_combinator(Combinator::Descendant, Selector::new(Some("ul".to_string()), None, Vec::new()))
+                        )
+                ),
+            )]
+            fn test_parse_compounds(input: &str, expected: Selector) {
+                let mut css_parser = CSSParser::new(input.to_string());
+
+                assert_eq!(css_parser.parse_selector().unwrap(), expected);
+            }
+
+            #[rstest(input,
+                case("div/**/span"),
+                case(".a/**/div"),
+            )]
+            fn test_fail_with_second_tag_in_compound(input: &str) {
+                let mut css_parser = CSSParser::new(input.to_string());
+
+                assert!(css_parser.parse_selector().is_err());
+            }
+        }
+
+        describe "'parse_selector' reads ':nth-child()' formulas" {
+            #[rstest(input, a, b,
+                case("li:nth-child(odd)", 2, 1),
+                case("li:nth-child(EVEN)", 2, 0),
+                case("li:nth-child(2n+1)", 2, 1),
+                case("li:nth-child(2n)", 2, 0),
+                case("li:nth-child(3)", 0, 3),
+                case("li:nth-child(n)", 1, 0),
+                case("li:nth-child(+n)", 1, 0),
+                case("li:nth-child(-n+3)", -1, 3),
+                case("li:nth-child(-2n-1)", -2, -1),
+                case("li:nth-child( 3n - 2 )", 3, -2),
+            )]
+            fn test_parse_nth_child(input: &str, a: i32, b: i32) {
+                assert_eq!(
+                    parse_selector(input).unwrap(),
+                    Selector::new(Some("li".to_string()), None, Vec::new())
+                        .with_pseudo_class(PseudoClass::NthChild(NthFormula::new(a, b)))
+                );
+            }
+
+            #[rstest(input, expected,
+                case("li:nth-child(2x)", "invalid :nth-child() argument '2x' at 1:14"),
+                case("li:nth-child(2n1)", "invalid :nth-child() argument '2n1' at 1:14"),
+                case("li:nth-child()", "invalid :nth-child() argument '' at 1:14"),
+                case("li:nth-child(2n", "unterminated :nth-child() at 1:16"),
+                case("a:hover", "unknown pseudo-class ':hover' at 1:3"),
+                case("a:", "expected a pseudo-class, found end of input at 1:3"),
+            )]
+            fn test_fail_with_invalid_nth_child(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' fails instead of panicking on truncated input" {
+            #[rstest]
+            fn test_every_prefix() {
+                let input = "ul#main > li.a\\31 :nth-of-type(2n+1):lang(\"en\") ~ p::before";
+                for end in (0..=input.len()).filter(|&end| input.is_char_boundary(end)) {
+                    let _ = parse_selector(&input[..end]);
+                }
+                assert!(parse_selector(input).is_ok());
+            }
+
+            #[rstest(input, expected,
+                case("", "expected a selector at 1:1"),
+                case("p.", "expected an identifier, found end of input at 1:3"),
+                case("p:nth-of-type(", "unterminated :nth-of-type() at 1:15"),
+                case("p:lang(", "expected a language, found end of input at 1:8"),
+            )]
+            fn test_fail_at_eof(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' reads ':lang()'" {
+            #[rstest(input, expected,
+                case("p:lang(en)", "en"),
+                case("p:lang( en-US )", "en-US"),
+                case("p:LANG(\"fr\")", "fr"),
+            )]
+            fn test_parse_lang(input: &str, expected: &str) {
+                assert_eq!(
+                    parse_selector(input).unwrap(),
+                    Selector::new(Some("p".to_string()), None, Vec::new()).with_pseudo_class(PseudoClass::Lang(expected.to_string()))
+                );
+            }
+
+            #[rstest(input, expected,
+                case("p:lang()", "expected a language, found ')' at 1:8"),
+                case("p:lang(en", "expected ')', found end of input at 1:10"),
+                case("p:lang(en fr)", "expected ')', found 'fr' at 1:11"),
+            )]
+            fn test_fail_with_invalid_lang(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' reads '::before' and '::after' as pseudo-elements" {
+            #[rstest(input, expected,
+                case(".x::before", Selector::new(None, None, Vec::from(["x".to_string()])).with_pseudo_element(PseudoElement::Before)),
+                case("p:first-of-type::AFTER", Selector::new(Some("p".to_string()), None, Vec::new()).with_pseudo_class(PseudoClass::FirstOfType).with_pseudo_element(PseudoElement::After)),
+                case(
+                    "div > p::before",
+                    Selector::new(Some("p".to_string()), None, Vec::new())
+                        .with_pseudo_element(PseudoElement::Before)
+                        .with_combinator(Combinator::Child, Selector::new(Some("div".to_string()), None, Vec::new()))
+                ),
+            )]
+            fn test_parse_pseudo_element(input: &str, expected: Selector) {
+                let selector = parse_selector(input).unwrap();
+                assert_eq!(selector, expected);
+                assert!(selector.pseudo_classes.iter().all(|pseudo_class| *pseudo_class == PseudoClass::FirstOfType));
+                assert_eq!(parse_selector(&selector.to_string()).unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case("p::marker", "unknown pseudo-element '::marker' at 1:4"),
+                case("p::", "expected a pseudo-element, found end of input at 1:4"),
+                case("p::before.x", "a pseudo-element must come last in a selector at 1:10"),
+                case("p::before span", "a pseudo-element must come last in a selector at 1:11"),
+                case("p::before > span", "a pseudo-element must come last in a selector at 1:13"),
+            )]
+            fn test_fail_with_misplaced_pseudo_element(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' reads the '-of-type' pseudo-classes" {
+            #[rstest(input, expected,
+                case("p:first-of-type", PseudoClass::FirstOfType),
+                case("p:LAST-OF-TYPE", PseudoClass::LastOfType),
+                case("p:nth-of-type(2n+1)", PseudoClass::NthOfType(NthFormula::new(2, 1))),
+                case("p:nth-of-type(2n)", PseudoClass::NthOfType(NthFormula::new(2, 0))),
+            )]
+            fn test_parse_of_type(input: &str, expected: PseudoClass) {
+                let selector = parse_selector(input).unwrap();
+                assert_eq!(selector, Selector::new(Some("p".to_string()), None, Vec::new()).with_pseudo_class(expected));
+                assert_eq!(selector.to_string(), input.to_ascii_lowercase());
+            }
+
+            #[rstest]
+            fn reports_the_function_name() {
+                assert_eq!(parse_selector("p:nth-of-type(x)").unwrap_err().to_string(), "invalid :nth-of-type() argument 'x' at 1:15");
+            }
+        }
+
+        describe "'parse_selector' reads '*' as the universal selector" {
+            #[rstest(input, expected,
+                case("*", Selector::new(None, None, Vec::new()).with_universal()),
+                case("*.a", Selector::new(None, None, Vec::from(["a".to_string()])).with_universal()),
+                case("*::before", Selector::new(None, None, Vec::new()).with_universal().with_pseudo_element(PseudoElement::Before)),
+                case(
+                    "ul > * li",
+                    Selector::new(Some("li".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Descendant, Selector::new(None, None, Vec::new()).with_universal()
+                            .with_combinator(Combinator::Child, Selector::new(Some("ul".to_string()), None, Vec::new())))
+                ),
+            )]
+            fn test_parse_universal(input: &str, expected: Selector) {
+                let selector = parse_selector(input).unwrap();
+                assert_eq!(selector, expected);
+                assert_eq!(selector.to_string(), input);
+                assert_ne!(parse_selector(&input.replace('*', "")).ok(), Some(expected));
+            }
+
+            #[rstest(input, expected,
+                case("p*", "'*' must come first in a compound selector at 1:2"),
+                case(".a*", "'*' must come first in a compound selector at 1:3"),
+                case("**", "'*' must come first in a compound selector at 1:2"),
+                case("*p", "a tag name must come first in a compound selector, found 'p' at 1:2"),
+            )]
+            fn test_fail_with_misplaced_universal(input: &str, expected: &str) {
+                assert_eq!(parse_selector(input).unwrap_err().to_string(), expected);
+            }
+
+            #[rstest(data, expected,
+                case("{ color: red; }", "expected a selector, found '{' at 1:1"),
+                case("a, { color: red; }", "expected a selector, found '{' at 1:4"),
+                case("a > { color: red; }", "expected a selector, found '{' at 1:5"),
+            )]
+            fn test_fail_with_empty_compound(data: &str, expected: &str) {
+                assert_eq!(parse(data.to_string()).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_selector' reads ':root'" {
+            #[rstest(input, expected,
+                case(":root", Selector::new(None, None, Vec::new()).with_pseudo_class(PseudoClass::Root)),
+                case("html:ROOT", Selector::new(Some("html".to_string()), None, Vec::new()).with_pseudo_class(PseudoClass::Root)),
+                case(
+                    ":root > body",
+                    Selector::new(Some("body".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Child, Selector::new(None, None, Vec::new()).with_pseudo_class(PseudoClass::Root))
+                ),
+            )]
+            fn test_parse_root(input: &str, expected: Selector) {
+                assert_eq!(parse_selector(input).unwrap(), expected);
             }
         }
 
@@ -265,12 +1760,28 @@ mod tests {
             fn test_empty_block() {
                 let mut css_parser = CSSParser::new("{}".to_string());
 
-                assert_eq!(css_parser.parse_declarations(), Vec::new());
+                assert_eq!(css_parser.parse_declarations().unwrap(), Vec::new());
             }
 
             #[rstest(input, expected,
                 case("{}", Vec::new()),
+                case(
+                    "{ font-family: Arial, 'Helvetica Neue', sans-serif; }",
+                    Vec::from([Declaration::new("font-family".to_string(), Value::CommaList(Vec::from([
+                        Value::keyword("Arial".to_string()),
+                        Value::String("Helvetica Neue".to_string()),
+                        Value::keyword("sans-serif".to_string()),
+                    ])))])
+                ),
                 case("{ display: block; }", Vec::from([Declaration::new("display".to_string(), Value::Keyword("block".to_string()))])),
+                case(
+                    "{ z-index: 5; opacity: 0.5; line-height: 1.5; }",
+                    Vec::from([
+                        Declaration::new("z-index".to_string(), Value::Number(5.0)),
+                        Declaration::new("opacity".to_string(), Value::Number(0.5)),
+                        Declaration::new("line-height".to_string(), Value::Number(1.5)),
+                    ])
+                ),
                 case(
                     "{ border-width: 1px; border-style: solid; border-color: #123456; background-color: red; }",
                     Vec::from([
@@ -284,7 +1795,190 @@ mod tests {
             fn test_parse_declarations(input: &str, expected: Vec<Declaration>) {
                 let mut css_parser = CSSParser::new(input.to_string());
 
-                assert_eq!(css_parser.parse_declarations(), expected);
+                assert_eq!(css_parser.parse_declarations().unwrap(), expected);
+            }
+        }
+
+        describe "'parse_declarations' reads custom properties and 'var()'" {
+            #[rstest(input, expected,
+                case(
+                    "{ --main-color: red; color: var(--main-color); }",
+                    Vec::from([
+                        Declaration::new("--main-color".to_string(), Value::Raw("red".to_string())),
+                        Declaration::new("color".to_string(), Value::Var("--main-color".to_string(), None)),
+                    ])
+                ),
+                case(
+                    "{ --shadow:  0 1px  rgb(0, 0, 0) ; --empty:; }",
+                    Vec::from([
+                        Declaration::new("--shadow".to_string(), Value::Raw("0 1px  rgb(0, 0, 0)".to_string())),
+                        Declaration::new("--empty".to_string(), Value::Raw("".to_string())),
+                    ])
+                ),
+                case(
+                    "{ margin: var( --gap , 1em ); font-family: var(--font, Arial, serif), monospace; }",
+                    Vec::from([
+                        Declaration::new("margin".to_string(), Value::Var("--gap".to_string(), Some(Box::new(Value::size(1.0, Unit::Em))))),
+                        Declaration::new("font-family".to_string(), Value::CommaList(Vec::from([
+                            Value::Var("--font".to_string(), Some(Box::new(Value::CommaList(Vec::from([
+                                Value::keyword("Arial".to_string()),
+                                Value::keyword("serif".to_string()),
+                            ]))))),
+                            Value::keyword("monospace".to_string()),
+                        ]))),
+                    ])
+                ),
+                case(
+                    "{ color: var(--a, var(--b, blue)); }",
+                    Vec::from([
+                        Declaration::new("color".to_string(), Value::Var(
+                            "--a".to_string(),
+                            Some(Box::new(Value::Var("--b".to_string(), Some(Box::new(Value::keyword("blue".to_string())))))),
+                        )),
+                    ])
+                ),
+            )]
+            fn test_custom_properties(input: &str, expected: Vec<Declaration>) {
+                let mut css_parser = CSSParser::new(input.to_string());
+
+                assert_eq!(css_parser.parse_declarations().unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case("var(main-color)", "expected a custom property name in var() at 1:1"),
+                case("var()", "expected a custom property name in var() at 1:1"),
+                case("var(--a, 1foo)", "unknown unit 'foo' at 1:1"),
+            )]
+            fn test_fail_with_invalid_var(input: &str, expected: &str) {
+                assert_eq!(parse_value(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_declarations' expands the font shorthand" {
+            #[rstest(input, expected,
+                case(
+                    "{ font: italic bold 16px/1.5 Arial; }",
+                    Vec::from([
+                        Declaration::new("font-style".to_string(), Value::keyword("italic".to_string())),
+                        Declaration::new("font-weight".to_string(), Value::keyword("bold".to_string())),
+                        Declaration::new("font-size".to_string(), Value::size(16.0, Unit::Px)),
+                        Declaration::new("font-family".to_string(), Value::keyword("Arial".to_string())),
+                    ])
+                ),
+                case(
+                    "{ font: small-caps 700 large / 20px \"Helvetica Neue\", sans-serif; color: red; }",
+                    Vec::from([
+                        Declaration::new("font-weight".to_string(), Value::Number(700.0)),
+                        Declaration::new("font-size".to_string(), Value::keyword("large".to_string())),
+                        Declaration::new("font-family".to_string(), Value::CommaList(Vec::from([
+                            Value::String("Helvetica Neue".to_string()),
+                            Value::keyword("sans-serif".to_string()),
+                        ]))),
+                        Declaration::new("color".to_string(), Value::keyword("red".to_string())),
+                    ])
+                ),
+                case(
+                    "{ font: 1.2em serif; }",
+                    Vec::from([
+                        Declaration::new("font-size".to_string(), Value::size(1.2, Unit::Em)),
+                        Declaration::new("font-family".to_string(), Value::keyword("serif".to_string())),
+                    ])
+                ),
+            )]
+            fn test_font_shorthand(input: &str, expected: Vec<Declaration>) {
+                let mut css_parser = CSSParser::new(input.to_string());
+
+                assert_eq!(css_parser.parse_declarations().unwrap(), expected);
+            }
+        }
+
+        describe "'parse_declarations' reads '!important'" {
+            #[rstest(input, expected,
+                case(
+                    "{ color: red !important; margin: 1px ! IMPORTANT; display: block; }",
+                    Vec::from([
+                        Declaration::new_important("color".to_string(), Value::keyword("red".to_string())),
+                        Declaration::new_important("margin".to_string(), Value::size(1.0, Unit::Px)),
+                        Declaration::new("display".to_string(), Value::keyword("block".to_string())),
+                    ])
+                ),
+                case(
+                    "{ --accent: #fff !important; }",
+                    Vec::from([
+                        Declaration::new_important("--accent".to_string(), Value::Raw("#fff".to_string())),
+                    ])
+                ),
+                case(
+                    "{ font: 12px serif !important; }",
+                    Vec::from([
+                        Declaration::new_important("font-size".to_string(), Value::size(12.0, Unit::Px)),
+                        Declaration::new_important("font-family".to_string(), Value::keyword("serif".to_string())),
+                    ])
+                ),
+            )]
+            fn test_important(input: &str, expected: Vec<Declaration>) {
+                let mut css_parser = CSSParser::new(input.to_string());
+
+                assert_eq!(css_parser.parse_declarations().unwrap(), expected);
+            }
+
+            #[rstest]
+            fn test_important_affects_equality() {
+                let value = Value::keyword("red".to_string());
+
+                assert_ne!(
+                    Declaration::new("color".to_string(), value.clone()),
+                    Declaration::new_important("color".to_string(), value)
+                );
+            }
+        }
+
+        describe "'parse_inline_style' parses the declarations of a style attribute" {
+            #[rstest(input, expected,
+                case("", Vec::new()),
+                case(
+                    "color: red; margin: 0 !important",
+                    Vec::from([
+                        Declaration::new("color".to_string(), Value::keyword("red".to_string())),
+                        Declaration::new_important("margin".to_string(), Value::Number(0.0)),
+                    ])
+                ),
+                case(
+                    " font-size: 2em; ",
+                    Vec::from([Declaration::new("font-size".to_string(), Value::size(2.0, Unit::Em))])
+                ),
+            )]
+            fn test_parse_inline_style(input: &str, expected: Vec<Declaration>) {
+                assert_eq!(parse_inline_style(input).unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case("color red", "expected ':', found 'red' at 1:7"),
+                case("color: red }", "expected an identifier, found '}' at 1:12"),
+            )]
+            fn test_fail_with_invalid_inline_style(input: &str, expected: &str) {
+                assert_eq!(parse_inline_style(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'parse_declarations' is not confused by strings and comments" {
+            #[rstest]
+            fn test_semicolon_inside_string() {
+                let mut css_parser = CSSParser::new("{ content: \"a;b\"; display: block; }".to_string());
+                let declarations = css_parser.parse_declarations().unwrap();
+
+                assert_eq!(declarations.len(), 2);
+                assert_eq!(declarations[1], Declaration::new("display".to_string(), Value::keyword("block".to_string())));
+            }
+
+            #[rstest]
+            fn test_comments_anywhere() {
+                let mut css_parser = CSSParser::new("/* head */ a /* x */ { /* y */ display: /* z */ block /* w */; }".to_string());
+
+                assert_eq!(css_parser.parse_rule().unwrap(), Rule::new(
+                    Vec::from([Selector::new(Some("a".to_string()), None, Vec::new())]),
+                    Vec::from([Declaration::new("display".to_string(), Value::keyword("block".to_string()))])
+                ));
             }
         }
 
@@ -307,7 +2001,7 @@ mod tests {
             fn test_rule(input: &str, expected: Rule) {
                 let mut css_parser = CSSParser::new(input.to_string());
 
-                assert_eq!(css_parser.parse_rule(), expected);
+                assert_eq!(css_parser.parse_rule().unwrap(), expected);
             }
         }
 
@@ -334,7 +2028,226 @@ mod tests {
                 )
             )]
             fn test_parse(data: &str, expected: StyleSheet) {
-                assert_eq!(parse(data.to_string()), expected);
+                assert_eq!(parse(data.to_string()).unwrap(), expected);
+            }
+
+            #[rstest(query, expected,
+                case("screen", MediaQuery::new(Vec::new())),
+                case("(prefers-color-scheme: dark)", MediaQuery::new(Vec::from([MediaFeature::PrefersColorScheme(ColorScheme::Dark)]))),
+                case(
+                    "only screen and (min-width: 40em) and (ORIENTATION: Portrait)",
+                    MediaQuery::new(Vec::from([MediaFeature::MinWidth(640.0), MediaFeature::Orientation(Orientation::Portrait)]))
+                ),
+                case(
+                    "(max-width:600px)and (prefers-color-scheme: light)",
+                    MediaQuery::new(Vec::from([MediaFeature::MaxWidth(600.0), MediaFeature::PrefersColorScheme(ColorScheme::Light)]))
+                ),
+            )]
+            fn test_parse_media_rule(query: &str, expected: MediaQuery) {
+                let stylesheet = parse(format!("a {{ color: red; }} @media {query} {{ a {{ color: blue; }} p {{ margin: 0; }} }}")).unwrap();
+
+                assert_eq!(stylesheet.rules.len(), 3);
+                assert_eq!(stylesheet.rules[0].media, None);
+                assert_eq!(stylesheet.rules[1].media, Some(expected.clone()));
+                assert_eq!(stylesheet.rules[2].media, Some(expected));
+            }
+
+            #[rstest(query,
+                case("print"),
+                case("screen, print"),
+                case("(hover: hover)"),
+                case("(min-width: 10%)"),
+                case("(prefers-color-scheme: blue)"),
+                case("screen (min-width: 1px)"),
+                case("screen and"),
+            )]
+            fn test_skip_unsupported_media_query(query: &str) {
+                let stylesheet = parse(format!("@media {query} {{ a {{ color: blue; }} }} p {{ margin: 0; }}")).unwrap();
+
+                assert_eq!(stylesheet.rules.len(), 1);
+                assert_eq!(stylesheet.rules[0].media, None);
+            }
+
+            #[rstest]
+            fn test_parse_skips_at_rules() {
+                let data = "
+                    @import url(\"base.css\");
+                    a { color: red; }
+                    @font-face { font-family: Foo; src: url(foo.woff) format(\"woff\"); }
+                    @keyframes spin { from { width: 0px; } to { width: 10px; } }
+                    p { display: block; }
+                ";
+
+                assert_eq!(parse(data.to_string()).unwrap(), StyleSheet::new(Vec::from([
+                    Rule::new(
+                        Vec::from([Selector::new(Some("a".to_string()), None, Vec::new())]),
+                        Vec::from([Declaration::new("color".to_string(), Value::keyword("red".to_string()))])
+                    ),
+                    Rule::new(
+                        Vec::from([Selector::new(Some("p".to_string()), None, Vec::new())]),
+                        Vec::from([Declaration::new("display".to_string(), Value::keyword("block".to_string()))])
+                    ),
+                ])));
+            }
+
+            #[rstest]
+            fn test_parse_stray_close_paren() {
+                let stylesheet = parse("a { color: red); background: blue } b { color: green }".to_string()).unwrap();
+
+                assert_eq!(stylesheet.rules.len(), 2);
+                assert_eq!(stylesheet.rules[0].declarations[1], Declaration::new("background".to_string(), Value::keyword("blue".to_string())));
+                assert_eq!(stylesheet.rules[1], Rule::new(
+                    Vec::from([Selector::new(Some("b".to_string()), None, Vec::new())]),
+                    Vec::from([Declaration::new("color".to_string(), Value::keyword("green".to_string()))])
+                ));
+                assert_eq!(
+                    parse_value("a), b").unwrap(),
+                    Value::CommaList(Vec::from([Value::keyword("a)".to_string()), Value::keyword("b".to_string())]))
+                );
+            }
+
+            #[rstest(data,
+                case(""),
+                case("   \n  "),
+                case("\n/* nothing here */\n"),
+            )]
+            fn test_parse_empty(data: &str) {
+                assert_eq!(parse(data.to_string()).unwrap(), StyleSheet::new(Vec::new()));
+            }
+
+            #[rstest(data, expected,
+                case("a { color: #12345z; }", CssParseError { message: "invalid color '#12345z'".to_string(), line: 1, column: 12 }),
+                case("a {\n  display: block;\n  width: 1abc;\n}", CssParseError { message: "unknown unit 'abc'".to_string(), line: 3, column: 10 }),
+                case("a {}\n@font-face { src: x;", CssParseError { message: "unterminated at-rule".to_string(), line: 2, column: 1 }),
+                case("a { color: ; }", CssParseError { message: "missing value".to_string(), line: 1, column: 12 }),
+                case("a { display block; }", CssParseError { message: "expected ':', found 'block'".to_string(), line: 1, column: 13 }),
+                case("a { display: block;", CssParseError { message: "expected an identifier, found end of input".to_string(), line: 1, column: 20 }),
+            )]
+            fn test_parse_error(data: &str, expected: CssParseError) {
+                assert_eq!(parse(data.to_string()), Err(expected));
+            }
+
+            #[rstest]
+            fn test_parse_with_diagnostics() {
+                let (result, warnings) = parse_with_diagnostics("a { color: red; width: 1abc; }\nb { colr: blue; }".to_string());
+
+                assert_eq!(
+                    result,
+                    Ok(StyleSheet::new(Vec::from([
+                        Rule::new(
+                            Vec::from([Selector::new(Some("a".to_string()), None, Vec::new())]),
+                            Vec::from([Declaration::new("color".to_string(), Value::keyword("red".to_string()))])
+                        ),
+                        Rule::new(
+                            Vec::from([Selector::new(Some("b".to_string()), None, Vec::new())]),
+                            Vec::from([Declaration::new("colr".to_string(), Value::keyword("blue".to_string()))])
+                        ),
+                    ])))
+                );
+                assert_eq!(
+                    warnings.iter().map(|warning| warning.to_string()).collect::<Vec<_>>(),
+                    Vec::from([
+                        "dropped malformed declaration (unknown unit 'abc') at 1:17".to_string(),
+                        "unknown property 'colr' at 2:5".to_string(),
+                    ])
+                );
+            }
+
+            #[rstest(data, expected,
+                case("a { display block; color: red }", "dropped malformed declaration (expected ':', found 'block') at 1:5"),
+                case("a { ; color: red }", "dropped malformed declaration (expected an identifier, found ';') at 1:5"),
+                case("a { color: #12345z; display: block }", "dropped malformed declaration (invalid color '#12345z') at 1:5"),
+            )]
+            fn test_diagnostics_recovers_from_malformed_declaration(data: &str, expected: &str) {
+                let (result, warnings) = parse_with_diagnostics(data.to_string());
+
+                assert!(result.is_ok(), "{result:?}");
+                assert_eq!(warnings.len(), 1);
+                assert_eq!(warnings[0].to_string(), expected);
+            }
+
+            #[rstest]
+            fn test_diagnostics_keeps_fatal_errors() {
+                let (result, warnings) = parse_with_diagnostics("a { colr: red; }\nb { display: block;".to_string());
+
+                assert!(result.is_err());
+                assert_eq!(warnings.len(), 1);
+                assert!(parse("a { width: 1abc; }".to_string()).is_err());
+            }
+
+            #[rstest]
+            fn test_diagnostics_recovers_after_stray_close_paren() {
+                let (result, warnings) = parse_with_diagnostics("a { width: calc(1px)); color: red; }\nb { color: green; }".to_string());
+
+                assert_eq!(
+                    result,
+                    Ok(StyleSheet::new(Vec::from([
+                        Rule::new(
+                            Vec::from([Selector::new(Some("a".to_string()), None, Vec::new())]),
+                            Vec::from([Declaration::new("color".to_string(), Value::keyword("red".to_string()))])
+                        ),
+                        Rule::new(
+                            Vec::from([Selector::new(Some("b".to_string()), None, Vec::new())]),
+                            Vec::from([Declaration::new("color".to_string(), Value::keyword("green".to_string()))])
+                        ),
+                    ])))
+                );
+                assert_eq!(
+                    warnings.iter().map(|warning| warning.to_string()).collect::<Vec<_>>(),
+                    Vec::from(["dropped malformed declaration (unexpected ')' in calc()) at 1:5".to_string()])
+                );
+            }
+
+            #[rstest]
+            fn test_input_size_limit() {
+                let limits = ParserLimits { max_input_bytes: 10, ..ParserLimits::default() };
+
+                assert_eq!(
+                    parse_with_limits("a { color: red; }".to_string(), limits.clone()),
+                    Err(CssParseError { message: "input is 17 bytes, over the limit of 10".to_string(), line: 1, column: 1 })
+                );
+                assert!(parse_with_limits("a {}".to_string(), limits).is_ok());
+            }
+
+            #[rstest]
+            fn test_diagnostics_input_size_limit() {
+                let max_input_bytes = ParserLimits::default().max_input_bytes;
+                let (result, warnings) = parse_with_diagnostics("a".repeat(max_input_bytes + 1));
+
+                assert_eq!(
+                    result,
+                    Err(CssParseError { message: format!("input is {} bytes, over the limit of {max_input_bytes}", max_input_bytes + 1), line: 1, column: 1 })
+                );
+                assert!(warnings.is_empty());
+            }
+
+            #[rstest(value, expected,
+                case("calc((((1px))))", Err("values are nested deeper than 2 at 1:12".to_string())),
+                case("calc(((1px)))", Ok(())),
+                case("calc(calc(calc(calc(1px))))", Err("values are nested deeper than 2 at 1:12".to_string())),
+                case("var(--a, var(--b, var(--c, red)))", Err("values are nested deeper than 2 at 1:12".to_string())),
+                case("var(--a, var(--b, red))", Ok(())),
+            )]
+            fn test_depth_limit(value: &str, expected: Result<(), String>) {
+                let limits = ParserLimits { max_depth: 2, ..ParserLimits::default() };
+                let result = parse_with_limits(format!("a {{ width: {value}; }}"), limits);
+
+                assert_eq!(result.map(|_| ()).map_err(|error| error.to_string()), expected);
+            }
+
+            #[rstest(prefix, open, suffix,
+                case("calc(", "(", ")"),
+                case("", "calc(", ""),
+                case("", "var(--a, ", ""),
+            )]
+            fn test_deep_nesting_fails(prefix: &str, open: &str, suffix: &str) {
+                let value = format!("{prefix}{}1px{}{suffix}", open.repeat(10_000), ")".repeat(10_000));
+
+                assert_eq!(
+                    parse(format!("a {{ width: {value}; }}")).unwrap_err().message,
+                    "values are nested deeper than 512"
+                );
+                assert!(parse_value(&value).is_err());
             }
         }
     }
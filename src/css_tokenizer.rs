@@ -0,0 +1,410 @@
+use std::fmt;
+
+use crate::parser::Parser;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CssToken {
+    Ident(String),
+    Function(String),
+    Hash(String),
+    AtKeyword(String),
+    Url(String),
+    Number(f32, String),
+    String(String),
+    Delim(char),
+    Whitespace,
+    Comment(String),
+    OpenBrace,
+    CloseBrace,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Colon,
+    Semicolon,
+    Comma,
+}
+
+struct CSSTokenizer {
+    base: Parser,
+}
+
+fn is_identifier_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
+}
+
+fn is_identifier_initial_char(c: char) -> bool {
+    matches!(c, 'a'..='z' | 'A'..='Z' | '_')
+}
+
+impl CSSTokenizer {
+    fn new(input: String) -> Self {
+        CSSTokenizer {
+            base: Parser::new(input),
+        }
+    }
+
+    fn next_is(&self, condition: impl Fn(char) -> bool) -> bool {
+        self.base.peek_char().is_some_and(condition)
+    }
+
+    fn starts_escape(&self) -> bool {
+        self.base.start_with("\\") && !self.base.start_with("\\\n")
+    }
+
+    // Reads the escape after a backslash: up to six hex digits and one optional
+    // whitespace for a code point, as in `\31 `, or else the next character as is.
+    fn consume_escape(&mut self) -> char {
+        let mut hex = String::new();
+        while hex.len() < 6 && self.next_is(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.base.consume_char());
+        }
+
+        if hex.is_empty() {
+            if self.base.eof() {
+                return char::REPLACEMENT_CHARACTER;
+            }
+            return self.base.consume_char();
+        }
+        if self.next_is(|c| c.is_whitespace()) {
+            self.base.consume_char();
+        }
+        match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+            Some('\0') | None => char::REPLACEMENT_CHARACTER,
+            Some(c) => c,
+        }
+    }
+
+    fn consume_identifier(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            if self.next_is(is_identifier_char) {
+                name.push(self.base.consume_char());
+            } else if self.starts_escape() {
+                self.base.consume_char();
+                name.push(self.consume_escape());
+            } else {
+                break;
+            }
+        }
+        name
+    }
+
+    fn consume_ident_like(&mut self) -> CssToken {
+        let name = self.consume_identifier();
+        if !self.next_is(|c| c == '(') {
+            return CssToken::Ident(name);
+        }
+
+        self.base.consume_char();
+        let whitespace = self.base.peek_while(|c| c.is_whitespace()).chars().count();
+        let is_quoted = matches!(self.base.peek_nth(whitespace), Some('"' | '\''));
+        if name.eq_ignore_ascii_case("url") && !is_quoted {
+            self.consume_url()
+        } else {
+            CssToken::Function(name)
+        }
+    }
+
+    // Reads an unquoted `url(...)` up to the closing paren. Its contents are
+    // taken as is, so `data:` URIs keep their `;` and `,`.
+    fn consume_url(&mut self) -> CssToken {
+        self.base.consume_whitespace();
+        let mut url = String::new();
+
+        while !self.base.eof() {
+            match self.base.consume_char() {
+                ')' => break,
+                '\\' if !self.next_is(|c| c == '\n') => url.push(self.consume_escape()),
+                c => url.push(c),
+            }
+        }
+
+        CssToken::Url(url.trim_end().to_string())
+    }
+
+    // Whether a number starts here, including forms like `-5`, `+.5` and `.5`.
+    fn starts_number(&self) -> bool {
+        let is_digit = |c: Option<char>| c.is_some_and(|c| c.is_ascii_digit());
+        let mut n = 0;
+        if matches!(self.base.peek_nth(n), Some('-' | '+')) {
+            n += 1;
+        }
+        if self.base.peek_nth(n) == Some('.') {
+            n += 1;
+        }
+        is_digit(self.base.peek_nth(n))
+    }
+
+    // Whether an identifier starts here. A `-` starts one only before another
+    // `-` or an identifier char, so that `1-2` reads as two numbers.
+    fn starts_identifier(&self) -> bool {
+        match self.base.peek_char() {
+            Some('-') => {
+                matches!(self.base.peek_nth(1), Some(c) if is_identifier_initial_char(c) || c == '-')
+                    || (self.base.peek_nth(1) == Some('\\') && self.base.peek_nth(2) != Some('\n'))
+            }
+            Some(c) => is_identifier_initial_char(c) || self.starts_escape(),
+            None => false,
+        }
+    }
+
+    fn consume_number(&mut self) -> CssToken {
+        let mut number = String::new();
+        if self.next_is(|c| c == '-' || c == '+') {
+            number.push(self.base.consume_char());
+        }
+        number.push_str(&self.base.consume_while(|c| c.is_ascii_digit()));
+        if self.next_is(|c| c == '.') {
+            number.push(self.base.consume_char());
+            number.push_str(&self.base.consume_while(|c| c.is_ascii_digit()));
+        }
+
+        let unit = if self.next_is(|c| c == '%') {
+            self.base.consume_char().to_string()
+        } else if self.starts_identifier() {
+            self.consume_identifier()
+        } else {
+            String::new()
+        };
+
+        CssToken::Number(number.parse::<f32>().unwrap(), unit)
+    }
+
+    fn consume_string(&mut self) -> CssToken {
+        let quote = self.base.consume_char();
+        let mut value = String::new();
+
+        while !self.base.eof() {
+            match self.base.consume_char() {
+                c if c == quote => break,
+                '\\' if !self.base.eof() => value.push(self.base.consume_char()),
+                c => value.push(c),
+            }
+        }
+
+        CssToken::String(value)
+    }
+
+    fn consume_comment(&mut self) -> CssToken {
+        self.base.consume_char();
+        self.base.consume_char();
+
+        let comment = self.base.consume_until("*/");
+        if !self.base.eof() {
+            self.base.consume_char();
+            self.base.consume_char();
+        }
+
+        CssToken::Comment(comment)
+    }
+
+    // `next` is the char at the cursor, which the caller has checked exists.
+    fn consume_token(&mut self, next: char) -> CssToken {
+        match next {
+            c if c.is_whitespace() => {
+                self.base.consume_whitespace();
+                CssToken::Whitespace
+            }
+            '0'..='9' | '.' | '-' | '+' if self.starts_number() => self.consume_number(),
+            _ if self.starts_identifier() => self.consume_ident_like(),
+            '"' | '\'' => self.consume_string(),
+            '/' if self.base.start_with("/*") => self.consume_comment(),
+            c => {
+                self.base.consume_char();
+                match c {
+                    '#' if self.next_is(is_identifier_char) || self.starts_escape() => {
+                        CssToken::Hash(self.consume_identifier())
+                    }
+                    '@' if self.starts_identifier() => {
+                        CssToken::AtKeyword(self.consume_identifier())
+                    }
+                    '{' => CssToken::OpenBrace,
+                    '}' => CssToken::CloseBrace,
+                    '(' => CssToken::OpenParen,
+                    ')' => CssToken::CloseParen,
+                    '[' => CssToken::OpenBracket,
+                    ']' => CssToken::CloseBracket,
+                    ':' => CssToken::Colon,
+                    ';' => CssToken::Semicolon,
+                    ',' => CssToken::Comma,
+                    _ => CssToken::Delim(c),
+                }
+            }
+        }
+    }
+
+    fn tokenize(&mut self) -> Vec<(CssToken, usize)> {
+        let mut tokens = Vec::new();
+
+        while let Some(next) = self.base.peek_char() {
+            let offset = self.base.position();
+            tokens.push((self.consume_token(next), offset));
+        }
+
+        tokens
+    }
+}
+
+impl fmt::Display for CssToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CssToken::Ident(name) => write!(f, "{name}"),
+            CssToken::Function(name) => write!(f, "{name}("),
+            CssToken::Hash(name) => write!(f, "#{name}"),
+            CssToken::AtKeyword(name) => write!(f, "@{name}"),
+            CssToken::Url(url) => write!(f, "url({url})"),
+            CssToken::Number(value, unit) => write!(f, "{value}{unit}"),
+            CssToken::String(value) => write!(f, "{value:?}"),
+            CssToken::Delim(c) => write!(f, "{c}"),
+            CssToken::Whitespace => write!(f, " "),
+            CssToken::Comment(comment) => write!(f, "/*{comment}*/"),
+            CssToken::OpenBrace => write!(f, "{{"),
+            CssToken::CloseBrace => write!(f, "}}"),
+            CssToken::OpenParen => write!(f, "("),
+            CssToken::CloseParen => write!(f, ")"),
+            CssToken::OpenBracket => write!(f, "["),
+            CssToken::CloseBracket => write!(f, "]"),
+            CssToken::Colon => write!(f, ":"),
+            CssToken::Semicolon => write!(f, ";"),
+            CssToken::Comma => write!(f, ","),
+        }
+    }
+}
+
+pub fn tokenize(input: &str) -> Vec<CssToken> {
+    tokenize_with_offsets(input)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect()
+}
+
+// Pairs each token with the byte offset where it starts in the input.
+pub fn tokenize_with_offsets(input: &str) -> Vec<(CssToken, usize)> {
+    let mut tokenizer = CSSTokenizer::new(input.to_string());
+    tokenizer.tokenize()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+    extern crate speculate;
+
+    use rstest::*;
+    use speculate::speculate;
+
+    use super::*;
+
+    speculate! {
+        describe "'tokenize' splits the input into tokens" {
+            #[rstest(input, expected,
+                case("div", Vec::from([CssToken::Ident("div".to_string())])),
+                case("-webkit-box", Vec::from([CssToken::Ident("-webkit-box".to_string())])),
+                case("--main-color", Vec::from([CssToken::Ident("--main-color".to_string())])),
+                case("#main", Vec::from([CssToken::Hash("main".to_string())])),
+                case("#12ab", Vec::from([CssToken::Hash("12ab".to_string())])),
+                case("@font-face", Vec::from([CssToken::AtKeyword("font-face".to_string())])),
+                case("@-webkit-keyframes", Vec::from([CssToken::AtKeyword("-webkit-keyframes".to_string())])),
+                case("@", Vec::from([CssToken::Delim('@')])),
+                case("\\31 0foo", Vec::from([CssToken::Ident("10foo".to_string())])),
+                case("a\\:b", Vec::from([CssToken::Ident("a:b".to_string())])),
+                case("\\000041\\42", Vec::from([CssToken::Ident("AB".to_string())])),
+                case("#\\31 0", Vec::from([CssToken::Hash("10".to_string())])),
+                case("data-1", Vec::from([CssToken::Ident("data-1".to_string())])),
+                case("10px", Vec::from([CssToken::Number(10.0, "px".to_string())])),
+                case("1.5em", Vec::from([CssToken::Number(1.5, "em".to_string())])),
+                case("43%", Vec::from([CssToken::Number(43.0, "%".to_string())])),
+                case("100", Vec::from([CssToken::Number(100.0, "".to_string())])),
+                case(".5%", Vec::from([CssToken::Number(0.5, "%".to_string())])),
+                case("-5%", Vec::from([CssToken::Number(-5.0, "%".to_string())])),
+                case("-.25em", Vec::from([CssToken::Number(-0.25, "em".to_string())])),
+                case("+3px", Vec::from([CssToken::Number(3.0, "px".to_string())])),
+                case("1-2", Vec::from([CssToken::Number(1.0, "".to_string()), CssToken::Number(-2.0, "".to_string())])),
+                case("-x", Vec::from([CssToken::Ident("-x".to_string())])),
+                case("- 1", Vec::from([CssToken::Delim('-'), CssToken::Whitespace, CssToken::Number(1.0, "".to_string())])),
+                case("\"a; b\"", Vec::from([CssToken::String("a; b".to_string())])),
+                case("'it\\'s'", Vec::from([CssToken::String("it's".to_string())])),
+                case("/* a { b } */", Vec::from([CssToken::Comment(" a { b } ".to_string())])),
+                case("rgb(", Vec::from([CssToken::Function("rgb".to_string())])),
+                case("url(a.png)", Vec::from([CssToken::Url("a.png".to_string())])),
+                case("URL( a\\).png  )", Vec::from([CssToken::Url("a).png".to_string())])),
+                case("url(data:image/png;base64,iVBOR==)", Vec::from([CssToken::Url("data:image/png;base64,iVBOR==".to_string())])),
+                case("url( \"a.png\")", Vec::from([
+                    CssToken::Function("url".to_string()), CssToken::Whitespace, CssToken::String("a.png".to_string()), CssToken::CloseParen,
+                ])),
+                case("  \n\t ", Vec::from([CssToken::Whitespace])),
+                case("{}()[]:;,", Vec::from([
+                    CssToken::OpenBrace, CssToken::CloseBrace,
+                    CssToken::OpenParen, CssToken::CloseParen,
+                    CssToken::OpenBracket, CssToken::CloseBracket,
+                    CssToken::Colon, CssToken::Semicolon, CssToken::Comma,
+                ])),
+                case(".>*-", Vec::from([
+                    CssToken::Delim('.'), CssToken::Delim('>'), CssToken::Delim('*'), CssToken::Delim('-'),
+                ])),
+            )]
+            fn test_tokenize_single_token(input: &str, expected: Vec<CssToken>) {
+                assert_eq!(tokenize(input), expected);
+            }
+
+            #[rstest]
+            fn test_tokenize_rule() {
+                assert_eq!(
+                    tokenize("a.b, #c { content: \"x;y\"; /* note */ }"),
+                    Vec::from([
+                        CssToken::Ident("a".to_string()),
+                        CssToken::Delim('.'),
+                        CssToken::Ident("b".to_string()),
+                        CssToken::Comma,
+                        CssToken::Whitespace,
+                        CssToken::Hash("c".to_string()),
+                        CssToken::Whitespace,
+                        CssToken::OpenBrace,
+                        CssToken::Whitespace,
+                        CssToken::Ident("content".to_string()),
+                        CssToken::Colon,
+                        CssToken::Whitespace,
+                        CssToken::String("x;y".to_string()),
+                        CssToken::Semicolon,
+                        CssToken::Whitespace,
+                        CssToken::Comment(" note ".to_string()),
+                        CssToken::Whitespace,
+                        CssToken::CloseBrace,
+                    ])
+                );
+            }
+
+            #[rstest(input,
+                case("\"unterminated"),
+                case("/* unterminated"),
+            )]
+            fn test_tokenize_unterminated_input_until_the_end(input: &str) {
+                assert_eq!(tokenize(input).len(), 1);
+            }
+        }
+
+        describe "'tokenize_with_offsets' records where each token starts" {
+            #[rstest]
+            fn test_offsets() {
+                let offsets: Vec<usize> = tokenize_with_offsets("a { b: 1px; }")
+                    .into_iter()
+                    .map(|(_, offset)| offset)
+                    .collect();
+
+                assert_eq!(offsets, Vec::from([0, 1, 2, 3, 4, 5, 6, 7, 10, 11, 12]));
+            }
+        }
+
+        describe "tokens are displayed as css text" {
+            #[rstest]
+            fn test_display_tokens() {
+                let text = tokenize("1px solid #abc")
+                    .iter()
+                    .map(|token| token.to_string())
+                    .collect::<String>();
+
+                assert_eq!(text, "1px solid #abc");
+            }
+        }
+    }
+}
@@ -1,16 +1,26 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 
-pub type AttributeMap = HashMap<String, String>;
+use indexmap::IndexMap;
 
-#[derive(Debug, PartialEq)]
+use crate::html::{encode_entities, EscapeContext};
+
+// Attributes keep their source order so that serializing a parsed document
+// writes them back in the same order.
+pub type AttributeMap = IndexMap<String, String>;
+
+// `tag_name` is what selectors match against. `original_tag_name` is only set
+// when a parser preserved a tag written in another case, like `<Div>`.
+#[derive(Clone, Debug, PartialEq)]
 pub struct ElementData {
     pub tag_name: String,
+    original_tag_name: Option<String>,
     attributes: AttributeMap,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum NodeType {
     Text(String),
+    Comment(String),
     Element(ElementData),
 }
 
@@ -20,14 +30,99 @@ pub struct Node {
     pub node_type: NodeType,
 }
 
+// What `sanitize` lets through. `allowed_tags` and `allowed_attributes` are
+// allowlists where `None` allows everything; `<script>`, `<style>`, `on*`
+// handlers and `javascript:` URLs are removed regardless.
+#[derive(Clone, Debug, Default)]
+pub struct SanitizePolicy {
+    pub allowed_tags: Option<HashSet<String>>,
+    pub allowed_attributes: Option<HashSet<String>>,
+    pub keep_text_of_removed: bool,
+}
+
+// One step of turning a tree into another. A node is addressed by its path,
+// the child indices from the root down, as the tree stands when the patch is
+// applied: patches are meant to be applied in the order `diff` returns them.
+#[derive(Debug, PartialEq)]
+pub enum Patch<'a> {
+    Replace {
+        path: Vec<usize>,
+        node: &'a Node,
+    },
+    SetAttribute {
+        path: Vec<usize>,
+        name: &'a str,
+        value: &'a str,
+    },
+    RemoveAttribute {
+        path: Vec<usize>,
+        name: &'a str,
+    },
+    // Sets the data of a text or comment node.
+    UpdateText {
+        path: Vec<usize>,
+        text: &'a str,
+    },
+    InsertChild {
+        path: Vec<usize>,
+        index: usize,
+        node: &'a Node,
+    },
+    RemoveChild {
+        path: Vec<usize>,
+        index: usize,
+    },
+}
+
+pub struct Element {
+    tag_name: String,
+    attributes: AttributeMap,
+    children: Vec<Node>,
+}
+
 impl ElementData {
     pub fn new(name: String, attributes: AttributeMap) -> Self {
         ElementData {
             tag_name: name,
-            attributes: attributes,
+            original_tag_name: None,
+            attributes,
         }
     }
 
+    // Keeps `name` for serialization and matches on its lowercase form.
+    pub fn with_original_tag_name(name: String, attributes: AttributeMap) -> Self {
+        let tag_name = name.to_ascii_lowercase();
+        ElementData {
+            original_tag_name: (tag_name != name).then_some(name),
+            tag_name,
+            attributes,
+        }
+    }
+
+    pub fn original_tag_name(&self) -> &str {
+        self.original_tag_name.as_ref().unwrap_or(&self.tag_name)
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(|value| value.as_str())
+    }
+
+    pub fn set_attribute(&mut self, name: &str, value: &str) -> bool {
+        if self.get_attribute(name) == Some(value) {
+            return false;
+        }
+        self.attributes.insert(name.to_string(), value.to_string());
+        true
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        self.attributes.shift_remove(name)
+    }
+
+    pub fn attributes(&self) -> &AttributeMap {
+        &self.attributes
+    }
+
     pub fn id(&self) -> Option<&String> {
         self.attributes.get("id")
     }
@@ -48,13 +143,777 @@ impl Node {
         }
     }
 
+    pub fn comment(data: String) -> Self {
+        Node {
+            children: vec![],
+            node_type: NodeType::Comment(data),
+        }
+    }
+
     pub fn element(name: String, attributes: AttributeMap, children: Vec<Node>) -> Self {
         Node {
-            children: children,
-            node_type: NodeType::Element(ElementData {
-                tag_name: name,
-                attributes: attributes,
-            }),
+            children,
+            node_type: NodeType::Element(ElementData::new(name, attributes)),
+        }
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        match self.node_type {
+            NodeType::Element(ref element_data) => element_data.get_attribute(name),
+            NodeType::Text(_) | NodeType::Comment(_) => None,
+        }
+    }
+
+    // Returns whether the attribute changed. Text nodes have no attributes.
+    pub fn set_attribute(&mut self, name: &str, value: &str) -> bool {
+        match self.node_type {
+            NodeType::Element(ref mut element_data) => element_data.set_attribute(name, value),
+            NodeType::Text(_) | NodeType::Comment(_) => false,
+        }
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) -> Option<String> {
+        match self.node_type {
+            NodeType::Element(ref mut element_data) => element_data.remove_attribute(name),
+            NodeType::Text(_) | NodeType::Comment(_) => None,
+        }
+    }
+
+    // The child list methods only change elements; text and comment nodes have
+    // no children. Out-of-range indices are rejected the same way.
+    pub fn append_child(&mut self, child: Node) -> bool {
+        let len = self.children.len();
+        self.insert_before(child, len)
+    }
+
+    // Inserts `child` so that it ends up at `index`. An index equal to the
+    // number of children appends.
+    pub fn insert_before(&mut self, child: Node, index: usize) -> bool {
+        if self.as_element().is_none() || index > self.children.len() {
+            return false;
+        }
+        self.children.insert(index, child);
+        true
+    }
+
+    pub fn remove_child(&mut self, index: usize) -> Option<Node> {
+        self.as_element()?;
+        (index < self.children.len()).then(|| self.children.remove(index))
+    }
+
+    pub fn replace_child(&mut self, index: usize, child: Node) -> Option<Node> {
+        self.as_element()?;
+        let old = self.children.get_mut(index)?;
+        Some(std::mem::replace(old, child))
+    }
+
+    pub fn as_element(&self) -> Option<&ElementData> {
+        match self.node_type {
+            NodeType::Element(ref element_data) => Some(element_data),
+            NodeType::Text(_) | NodeType::Comment(_) => None,
+        }
+    }
+
+    // Returns the descendant elements with the tag name in document order,
+    // leaving out this node itself as the DOM method does.
+    pub fn get_elements_by_tag_name(&self, tag_name: &str) -> Vec<&Node> {
+        let mut elements = Vec::new();
+        let mut stack: Vec<&Node> = self.children.iter().rev().collect();
+        while let Some(node) = stack.pop() {
+            if node.tag_name() == Some(tag_name) {
+                elements.push(node);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+        elements
+    }
+
+    pub fn tag_name(&self) -> Option<&str> {
+        match self.node_type {
+            NodeType::Element(ref element_data) => Some(&element_data.tag_name),
+            NodeType::Text(_) | NodeType::Comment(_) => None,
+        }
+    }
+
+    // Counts this node and all of its descendants, elements and text alike.
+    pub fn node_count(&self) -> usize {
+        let mut count = 0;
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            count += 1;
+            stack.extend(node.children.iter());
+        }
+        count
+    }
+
+    // The number of nodes on the longest path from this node down to a leaf,
+    // so a lone node has depth 1. Both walks use an explicit stack so that
+    // deeply nested trees cannot overflow the call stack.
+    pub fn depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((node, depth)) = stack.pop() {
+            max_depth = max_depth.max(depth);
+            stack.extend(node.children.iter().map(|child| (child, depth + 1)));
+        }
+        max_depth
+    }
+}
+
+impl Node {
+    // Serializes the tree as HTML, attributes in their source order and tags
+    // in their original case when the parser preserved it.
+    pub fn to_html(&self) -> String {
+        match self.node_type {
+            NodeType::Text(ref text) => encode_entities(text, EscapeContext::Text),
+            NodeType::Comment(ref comment) => format!("<!--{comment}-->"),
+            NodeType::Element(ref element_data) => {
+                let attributes: String = element_data
+                    .attributes
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = encode_entities(value, EscapeContext::DoubleQuotedAttribute);
+                        format!(" {name}=\"{value}\"")
+                    })
+                    .collect();
+                let children: String = self.children.iter().map(Node::to_html).collect();
+                let tag_name = element_data.original_tag_name();
+                format!("<{tag_name}{attributes}>{children}</{tag_name}>")
+            }
+        }
+    }
+
+    // The node including its own tag, as the DOM property of the same name.
+    pub fn outer_html(&self) -> String {
+        self.to_html()
+    }
+
+    // Just the serialized children of an element, so a void element like
+    // `<br>` gives an empty string. Text gives its escaped contents and a
+    // comment its raw text, as neither has children to serialize.
+    pub fn inner_html(&self) -> String {
+        match self.node_type {
+            NodeType::Text(ref text) => encode_entities(text, EscapeContext::Text),
+            NodeType::Comment(ref comment) => comment.clone(),
+            NodeType::Element(_) => self.children.iter().map(Node::to_html).collect(),
+        }
+    }
+}
+
+impl Element {
+    pub fn new(tag_name: &str) -> Self {
+        Element {
+            tag_name: tag_name.to_string(),
+            attributes: AttributeMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn attr(mut self, name: &str, value: &str) -> Self {
+        self.attributes.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn class(mut self, class: &str) -> Self {
+        let classes = match self.attributes.get("class") {
+            Some(classes) => format!("{classes} {class}"),
+            None => class.to_string(),
+        };
+        self.attributes.insert("class".to_string(), classes);
+        self
+    }
+
+    pub fn child(mut self, child: Node) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    pub fn text(self, data: &str) -> Self {
+        self.child(Node::text(data.to_string()))
+    }
+
+    pub fn build(self) -> Node {
+        Node::element(self.tag_name, self.attributes, self.children)
+    }
+}
+
+const ALWAYS_REMOVED_TAGS: [&str; 2] = ["script", "style"];
+
+fn is_javascript_url(value: &str) -> bool {
+    let value: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect();
+    value.to_ascii_lowercase().starts_with("javascript:")
+}
+
+impl SanitizePolicy {
+    fn allows_tag(&self, tag_name: &str) -> bool {
+        !ALWAYS_REMOVED_TAGS.contains(&tag_name)
+            && self
+                .allowed_tags
+                .as_ref()
+                .is_none_or(|tags| tags.contains(tag_name))
+    }
+
+    fn allows_attribute(&self, name: &str, value: &str) -> bool {
+        !name.to_ascii_lowercase().starts_with("on")
+            && !is_javascript_url(value)
+            && self
+                .allowed_attributes
+                .as_ref()
+                .is_none_or(|attributes| attributes.contains(name))
+    }
+}
+
+// Returns the nodes that replace `node` in the sanitized tree: the node itself
+// with its disallowed attributes removed, its children when the element is
+// unwrapped, or nothing.
+fn sanitize_node(node: Node, policy: &SanitizePolicy) -> Vec<Node> {
+    let children = node
+        .children
+        .into_iter()
+        .flat_map(|child| sanitize_node(child, policy));
+
+    match node.node_type {
+        NodeType::Text(_) => vec![Node {
+            children: Vec::new(),
+            node_type: node.node_type,
+        }],
+        NodeType::Comment(_) => Vec::new(),
+        NodeType::Element(ref element_data)
+            if ALWAYS_REMOVED_TAGS.contains(&element_data.tag_name.as_str()) =>
+        {
+            Vec::new()
+        }
+        NodeType::Element(ref element_data) if !policy.allows_tag(&element_data.tag_name) => {
+            if policy.keep_text_of_removed {
+                children.collect()
+            } else {
+                Vec::new()
+            }
+        }
+        NodeType::Element(mut element_data) => {
+            element_data
+                .attributes
+                .retain(|name, value| policy.allows_attribute(name, value));
+            vec![Node {
+                children: children.collect(),
+                node_type: NodeType::Element(element_data),
+            }]
+        }
+    }
+}
+
+// Removes scripts, styles, comments, event handler attributes and
+// `javascript:` URLs, plus whatever the policy does not allow. The root is
+// never unwrapped: if it is removed, an empty text node takes its place.
+pub fn sanitize(node: Node, policy: SanitizePolicy) -> Node {
+    let root_allowed = match node.node_type {
+        NodeType::Element(ref element_data) => policy.allows_tag(&element_data.tag_name),
+        NodeType::Text(_) => true,
+        NodeType::Comment(_) => false,
+    };
+    if !root_allowed {
+        return Node::text(String::new());
+    }
+
+    sanitize_node(node, &policy).remove(0)
+}
+
+fn diff_attributes<'a>(
+    old: &'a ElementData,
+    new: &'a ElementData,
+    path: &[usize],
+    patches: &mut Vec<Patch<'a>>,
+) {
+    for name in old.attributes.keys() {
+        if !new.attributes.contains_key(name) {
+            patches.push(Patch::RemoveAttribute {
+                path: path.to_vec(),
+                name,
+            });
+        }
+    }
+    for (name, value) in &new.attributes {
+        if old.attributes.get(name) != Some(value) {
+            patches.push(Patch::SetAttribute {
+                path: path.to_vec(),
+                name,
+                value,
+            });
+        }
+    }
+}
+
+// Children that are equal at the start and at the end are left alone, so a
+// single insertion or removal does not shift every later sibling. The rest
+// are compared pairwise by position, then the surplus is removed from the
+// back or inserted in order.
+fn diff_children<'a>(
+    old: &'a [Node],
+    new: &'a [Node],
+    path: &mut Vec<usize>,
+    patches: &mut Vec<Patch<'a>>,
+) {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    for (i, (old_child, new_child)) in old_middle.iter().zip(new_middle).enumerate() {
+        path.push(prefix + i);
+        diff_node(old_child, new_child, path, patches);
+        path.pop();
+    }
+
+    let paired = old_middle.len().min(new_middle.len());
+    for index in (prefix + paired..prefix + old_middle.len()).rev() {
+        patches.push(Patch::RemoveChild {
+            path: path.clone(),
+            index,
+        });
+    }
+    for (i, node) in new_middle[paired..].iter().enumerate() {
+        patches.push(Patch::InsertChild {
+            path: path.clone(),
+            index: prefix + paired + i,
+            node,
+        });
+    }
+}
+
+fn diff_node<'a>(
+    old: &'a Node,
+    new: &'a Node,
+    path: &mut Vec<usize>,
+    patches: &mut Vec<Patch<'a>>,
+) {
+    match (&old.node_type, &new.node_type) {
+        (NodeType::Text(old_text), NodeType::Text(text))
+        | (NodeType::Comment(old_text), NodeType::Comment(text)) => {
+            if old_text != text {
+                patches.push(Patch::UpdateText {
+                    path: path.clone(),
+                    text,
+                });
+            }
+        }
+        (NodeType::Element(old_data), NodeType::Element(data))
+            if old_data.tag_name == data.tag_name =>
+        {
+            diff_attributes(old_data, data, path, patches);
+            diff_children(&old.children, &new.children, path, patches);
+        }
+        _ => patches.push(Patch::Replace {
+            path: path.clone(),
+            node: new,
+        }),
+    }
+}
+
+// Computes the patches that turn `old` into `new`. Nodes of a different kind
+// or tag are replaced whole; otherwise only what differs is patched.
+pub fn diff<'a>(old: &'a Node, new: &'a Node) -> Vec<Patch<'a>> {
+    let mut patches = Vec::new();
+    diff_node(old, new, &mut Vec::new(), &mut patches);
+    patches
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate rstest;
+    extern crate speculate;
+
+    use rstest::*;
+    use speculate::speculate;
+
+    use super::*;
+    use crate::html;
+
+    speculate! {
+        describe "'Element' builds the same tree as 'Node::element'" {
+            #[rstest]
+            fn test_build_tree() {
+                let node = Element::new("div")
+                    .attr("id", "main")
+                    .class("box")
+                    .class("large")
+                    .child(Element::new("p").text("hello").build())
+                    .child(Element::new("a").attr("href", "/").build())
+                    .build();
+
+                assert_eq!(node, Node::element("div".to_string(), AttributeMap::from([
+                    ("id".to_string(), "main".to_string()),
+                    ("class".to_string(), "box large".to_string()),
+                ]), Vec::from([
+                    Node::element("p".to_string(), AttributeMap::new(), Vec::from([
+                        Node::text("hello".to_string())
+                    ])),
+                    Node::element("a".to_string(), AttributeMap::from([("href".to_string(), "/".to_string())]), Vec::new()),
+                ])));
+            }
+        }
+
+        describe "'get_attribute' reads an attribute of the node" {
+            #[rstest(name, expected,
+                case("id", Some("main")),
+                case("class", Some("box large")),
+                case("href", None),
+            )]
+            fn test_get_attribute_of_element(name: &str, expected: Option<&str>) {
+                let node = Node::element("div".to_string(), AttributeMap::from([
+                    ("id".to_string(), "main".to_string()),
+                    ("class".to_string(), "box large".to_string()),
+                ]), Vec::new());
+
+                assert_eq!(node.get_attribute(name), expected);
+            }
+
+            #[rstest]
+            fn test_get_attribute_of_text_is_none() {
+                let node = Node::text("id".to_string());
+
+                assert_eq!(node.get_attribute("id"), None);
+            }
+        }
+
+        describe "'set_attribute' and 'remove_attribute' mutate attributes of the node" {
+            #[rstest]
+            fn test_set_attribute() {
+                let mut node = Node::element("div".to_string(), AttributeMap::new(), Vec::new());
+
+                assert!(node.set_attribute("id", "main"));
+                assert_eq!(node.get_attribute("id"), Some("main"));
+
+                assert!(!node.set_attribute("id", "main"));
+
+                assert!(node.set_attribute("id", "sub"));
+                assert_eq!(node.get_attribute("id"), Some("sub"));
+            }
+
+            #[rstest]
+            fn test_remove_attribute() {
+                let mut node = Node::element("div".to_string(), AttributeMap::from([("id".to_string(), "main".to_string())]), Vec::new());
+
+                assert_eq!(node.remove_attribute("id"), Some("main".to_string()));
+                assert_eq!(node.get_attribute("id"), None);
+                assert_eq!(node.remove_attribute("id"), None);
+            }
+
+            #[rstest]
+            fn test_text_node_is_unchanged() {
+                let mut node = Node::text("hello".to_string());
+
+                assert!(!node.set_attribute("id", "main"));
+                assert_eq!(node.remove_attribute("id"), None);
+                assert_eq!(node, Node::text("hello".to_string()));
+            }
+        }
+
+        describe "child list methods edit the children of an element" {
+            #[rstest]
+            fn test_append_and_insert() {
+                let mut node = Element::new("ul").build();
+
+                assert!(node.append_child(Element::new("li").attr("id", "b").build()));
+                assert!(node.insert_before(Element::new("li").attr("id", "a").build(), 0));
+                assert!(node.append_child(Node::text("d".to_string())));
+                assert!(node.insert_before(Element::new("li").attr("id", "c").build(), 2));
+
+                assert_eq!(node.to_html(), "<ul><li id=\"a\"></li><li id=\"b\"></li><li id=\"c\"></li>d</ul>");
+            }
+
+            #[rstest]
+            fn test_remove_and_replace() {
+                let mut node = html::parse("<ul><li id='a'></li><li id='b'></li><li id='c'></li></ul>".to_string()).unwrap();
+
+                let removed = node.remove_child(1).unwrap();
+                assert_eq!(removed.get_attribute("id"), Some("b"));
+                assert_eq!(node.to_html(), "<ul><li id=\"a\"></li><li id=\"c\"></li></ul>");
+
+                let replaced = node.replace_child(0, Node::text("x".to_string())).unwrap();
+                assert_eq!(replaced.get_attribute("id"), Some("a"));
+                assert_eq!(node.to_html(), "<ul>x<li id=\"c\"></li></ul>");
+            }
+
+            #[rstest]
+            fn test_out_of_range_index_is_rejected() {
+                let mut node = html::parse("<ul><li></li></ul>".to_string()).unwrap();
+
+                assert!(!node.insert_before(Node::text("x".to_string()), 2));
+                assert_eq!(node.remove_child(1), None);
+                assert_eq!(node.replace_child(1, Node::text("x".to_string())), None);
+                assert_eq!(node.to_html(), "<ul><li></li></ul>");
+            }
+
+            #[rstest]
+            fn test_text_node_is_unchanged() {
+                let mut node = Node::text("hello".to_string());
+
+                assert!(!node.append_child(Node::text("x".to_string())));
+                assert!(!node.insert_before(Node::text("x".to_string()), 0));
+                assert_eq!(node.remove_child(0), None);
+                assert_eq!(node.replace_child(0, Node::text("x".to_string())), None);
+                assert_eq!(node, Node::text("hello".to_string()));
+            }
+        }
+
+        describe "'get_elements_by_tag_name' returns descendant elements" {
+            #[rstest]
+            fn test_get_elements_by_tag_name() {
+                let node = html::parse("<div><p id='a'>p<p id='b'></p></p><!-- p --><span><p id='c'></p></span></div>".to_string()).unwrap();
+
+                let ids: Vec<&str> = node
+                    .get_elements_by_tag_name("p")
+                    .into_iter()
+                    .map(|node| node.get_attribute("id").unwrap())
+                    .collect();
+
+                assert_eq!(ids, Vec::from(["a", "b", "c"]));
+                assert!(node.get_elements_by_tag_name("div").is_empty());
+            }
+
+            #[rstest]
+            fn test_skip_text_and_comments() {
+                let node = Element::new("div")
+                    .child(Node::comment("p".to_string()))
+                    .text("p")
+                    .build();
+
+                assert!(node.get_elements_by_tag_name("p").is_empty());
+            }
+        }
+
+        describe "'sanitize' removes dangerous content" {
+            #[rstest]
+            fn test_remove_scripts_and_handlers() {
+                let node = html::parse("<div onclick='steal()' id='x'><script>alert(1)</script><p>safe<style>p { color: red; }</style></p><a href=' JavaScript:alert(1)' title='ok'>link</a><!-- hidden --></div>".to_string()).unwrap();
+
+                let sanitized = sanitize(node, SanitizePolicy::default());
+
+                assert_eq!(sanitized.to_html(), "<div id=\"x\"><p>safe</p><a title=\"ok\">link</a></div>");
+            }
+
+            #[rstest(keep_text_of_removed, expected,
+                case(false, "<div><p>a</p></div>"),
+                case(true, "<div><p>a</p>b<i>c</i></div>"),
+            )]
+            fn test_allowlist(keep_text_of_removed: bool, expected: &str) {
+                let node = html::parse("<div><p class='x'>a</p><span>b<i>c</i></span><script>d</script></div>".to_string()).unwrap();
+                let policy = SanitizePolicy {
+                    allowed_tags: Some(HashSet::from(["div".to_string(), "p".to_string(), "i".to_string()])),
+                    allowed_attributes: Some(HashSet::new()),
+                    keep_text_of_removed,
+                };
+
+                assert_eq!(sanitize(node, policy).to_html(), expected);
+            }
+
+            #[rstest]
+            fn test_removed_root() {
+                let node = html::parse("<script>alert(1)</script>".to_string()).unwrap();
+
+                assert_eq!(sanitize(node, SanitizePolicy::default()), Node::text(String::new()));
+            }
+        }
+
+        describe "'outer_html' and 'inner_html' serialize a fragment" {
+            #[rstest]
+            fn test_element_with_children() {
+                let node = Element::new("ul")
+                    .attr("id", "list")
+                    .child(Element::new("li").text("a & b").build())
+                    .child(Node::comment("x".to_string()))
+                    .child(Element::new("li").child(Element::new("em").text("c").build()).build())
+                    .build();
+
+                assert_eq!(node.outer_html(), "<ul id=\"list\"><li>a &amp; b</li><!--x--><li><em>c</em></li></ul>");
+                assert_eq!(node.inner_html(), "<li>a &amp; b</li><!--x--><li><em>c</em></li>");
+                assert_eq!(node.children[2].inner_html(), "<em>c</em>");
+                assert_eq!(node.children[2].outer_html(), "<li><em>c</em></li>");
+            }
+
+            #[rstest(node, expected_outer, expected_inner,
+                case(Node::text("1 < 2".to_string()), "1 &lt; 2", "1 &lt; 2"),
+                case(Node::comment(" c ".to_string()), "<!-- c -->", " c "),
+                case(Element::new("br").build(), "<br></br>", ""),
+            )]
+            fn test_leaf(node: Node, expected_outer: &str, expected_inner: &str) {
+                assert_eq!(node.outer_html(), expected_outer);
+                assert_eq!(node.inner_html(), expected_inner);
+            }
+        }
+
+        describe "'to_html' serializes the tree" {
+            #[rstest]
+            fn test_to_html() {
+                let node = Element::new("p")
+                    .attr("title", "\"a\" & b")
+                    .class("x")
+                    .text("1 < 2 & 3")
+                    .child(Element::new("br").build())
+                    .build();
+
+                assert_eq!(node.to_html(), "<p title=\"&quot;a&quot; &amp; b\" class=\"x\">1 &lt; 2 &amp; 3<br></br></p>");
+            }
+
+            #[rstest]
+            fn test_comment_to_html() {
+                let node = Element::new("div")
+                    .child(Node::comment(" a < b ".to_string()))
+                    .text("hi")
+                    .build();
+
+                assert_eq!(node.to_html(), "<div><!-- a < b -->hi</div>");
+            }
+
+            #[rstest]
+            fn test_keep_attribute_order() {
+                let node = html::parse("<div b=\"1\" a=\"2\"><p z='' y='' x=''>hi</p></div>".to_string()).unwrap();
+
+                assert_eq!(node.to_html(), "<div b=\"1\" a=\"2\"><p z=\"\" y=\"\" x=\"\">hi</p></div>");
+            }
+
+            #[rstest]
+            fn test_remove_keeps_order() {
+                let mut node = Element::new("div").attr("a", "1").attr("b", "2").attr("c", "3").build();
+                node.remove_attribute("a");
+                node.set_attribute("a", "4");
+                node.set_attribute("b", "5");
+
+                assert_eq!(node.to_html(), "<div b=\"5\" c=\"3\" a=\"4\"></div>");
+            }
+        }
+
+        describe "'tag_name' returns the tag name of the node" {
+            #[rstest]
+            fn test_tag_name_of_element() {
+                let node = Node::element("p".to_string(), AttributeMap::new(), Vec::new());
+
+                assert_eq!(node.tag_name(), Some("p"));
+            }
+
+            #[rstest]
+            fn test_tag_name_of_text_is_none() {
+                let node = Node::text("p".to_string());
+
+                assert_eq!(node.tag_name(), None);
+            }
+        }
+
+        describe "'diff' computes the patches between two trees" {
+            #[rstest]
+            fn test_diff_identical_trees() {
+                let old = html::parse("<div id='a'><p>hi</p><!-- c --></div>".to_string()).unwrap();
+                let new = html::parse("<div id='a'><p>hi</p><!-- c --></div>".to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::new());
+            }
+
+            #[rstest]
+            fn test_diff_attributes() {
+                let old = html::parse("<div id='a' class='x'><p title='t'></p></div>".to_string()).unwrap();
+                let new = html::parse("<div id='b' lang='en'><p title='t'></p></div>".to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::from([
+                    Patch::RemoveAttribute { path: Vec::new(), name: "class" },
+                    Patch::SetAttribute { path: Vec::new(), name: "id", value: "b" },
+                    Patch::SetAttribute { path: Vec::new(), name: "lang", value: "en" },
+                ]));
+            }
+
+            #[rstest]
+            fn test_diff_text() {
+                let old = html::parse("<div><p>hello</p><!-- a --></div>".to_string()).unwrap();
+                let new = html::parse("<div><p>world</p><!-- b --></div>".to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::from([
+                    Patch::UpdateText { path: Vec::from([0, 0]), text: "world" },
+                    Patch::UpdateText { path: Vec::from([1]), text: " b " },
+                ]));
+            }
+
+            #[rstest(old, new, expected_index,
+                case("<ul><li>a</li><li>b</li></ul>", "<ul><li>a</li><li>b</li><li>c</li></ul>", 2),
+                case("<ul><li>a</li><li>b</li></ul>", "<ul><li>c</li><li>a</li><li>b</li></ul>", 0),
+                case("<ul><li>a</li><li>b</li></ul>", "<ul><li>a</li><li>c</li><li>b</li></ul>", 1),
+            )]
+            fn test_diff_added_child(old: &str, new: &str, expected_index: usize) {
+                let old = html::parse(old.to_string()).unwrap();
+                let new = html::parse(new.to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::from([
+                    Patch::InsertChild { path: Vec::new(), index: expected_index, node: &new.children[expected_index] },
+                ]));
+            }
+
+            #[rstest]
+            fn test_diff_removed_children() {
+                let old = html::parse("<ul><li>a</li><li>b</li><li>c</li><li>d</li></ul>".to_string()).unwrap();
+                let new = html::parse("<ul><li>a</li><li>d</li></ul>".to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::from([
+                    Patch::RemoveChild { path: Vec::new(), index: 2 },
+                    Patch::RemoveChild { path: Vec::new(), index: 1 },
+                ]));
+            }
+
+            #[rstest]
+            fn test_diff_replaced_nodes() {
+                let old = html::parse("<div><p>a</p><span>b</span>text</div>".to_string()).unwrap();
+                let new = html::parse("<div><p>a</p><em>b</em><i>x</i></div>".to_string()).unwrap();
+
+                assert_eq!(diff(&old, &new), Vec::from([
+                    Patch::Replace { path: Vec::from([1]), node: &new.children[1] },
+                    Patch::Replace { path: Vec::from([2]), node: &new.children[2] },
+                ]));
+                assert_eq!(diff(&old, &new.children[0]), Vec::from([
+                    Patch::Replace { path: Vec::new(), node: &new.children[0] },
+                ]));
+            }
+        }
+
+        describe "'node_count' and 'depth' measure the tree" {
+            #[rstest]
+            fn test_measure_tree() {
+                let node = Element::new("html")
+                    .child(Element::new("head").build())
+                    .child(
+                        Element::new("body")
+                            .child(Element::new("p").text("hello").build())
+                            .child(Element::new("p").child(Element::new("em").text("world").build()).build())
+                            .build(),
+                    )
+                    .build();
+
+                assert_eq!(node.node_count(), 8);
+                assert_eq!(node.depth(), 5);
+            }
+
+            #[rstest]
+            fn test_measure_single_node() {
+                let node = Node::text("hello".to_string());
+
+                assert_eq!(node.node_count(), 1);
+                assert_eq!(node.depth(), 1);
+            }
+
+            #[rstest]
+            fn test_measure_deep_tree() {
+                let mut node = Node::text("leaf".to_string());
+                for _ in 0..10_000 {
+                    node = Element::new("div").child(node).build();
+                }
+
+                assert_eq!(node.node_count(), 10_001);
+                assert_eq!(node.depth(), 10_001);
+            }
         }
     }
 }
@@ -0,0 +1,158 @@
+// Random inputs for checking that the parsers return errors instead of
+// panicking. The generator is a fixed-seed xorshift, so failures reproduce.
+struct RandomInput {
+    state: u64,
+}
+
+const HTML_FRAGMENTS: [&str; 24] = [
+    "<", ">", "</", "/>", "<!--", "-->", "=", "'", "\"", "&", ";", "&#", "&#x", "div", "p", "a",
+    " ", "\n", "x", "1", "é", "日本", "\u{0}", "🦀",
+];
+
+const CSS_FRAGMENTS: [&str; 43] = [
+    "{",
+    "}",
+    "(",
+    ")",
+    "[",
+    "]",
+    ":",
+    ";",
+    ",",
+    ".",
+    "#",
+    ">",
+    "+",
+    "-",
+    "*",
+    "/",
+    "\\",
+    "'",
+    "\"",
+    "@",
+    "/*",
+    "*/",
+    "%",
+    "!",
+    "url(",
+    "calc(",
+    "rgb(",
+    "hsl(",
+    ":nth-child(",
+    "n",
+    "a",
+    "px",
+    "em",
+    "1",
+    ".5",
+    "e",
+    " ",
+    "é",
+    "🦀",
+    "font",
+    "@media ",
+    "and",
+    "(min-width:",
+];
+
+impl RandomInput {
+    fn new(seed: u64) -> Self {
+        RandomInput { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // Mostly concatenates syntax fragments, with the odd arbitrary char mixed
+    // in, so the input gets past the first few checks of the parser.
+    fn string(&mut self, fragments: &[&str], max_len: usize) -> String {
+        let len = self.next() as usize % max_len;
+        let mut string = String::new();
+        for _ in 0..len {
+            let n = self.next();
+            if n.is_multiple_of(8) {
+                string.extend(char::from_u32((n >> 8) as u32 % 0x11_0000));
+            } else {
+                string.push_str(fragments[(n >> 8) as usize % fragments.len()]);
+            }
+        }
+        string
+    }
+}
+
+const RUNS: usize = 5000;
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, UnwindSafe};
+
+    use super::*;
+    use crate::html::{HtmlParseOptions, WhitespaceMode};
+    use crate::{css, html};
+
+    fn assert_no_panic<F>(input: &str, parse: F)
+    where
+        F: FnOnce() + UnwindSafe,
+    {
+        assert!(panic::catch_unwind(parse).is_ok(), "panicked on {input:?}");
+    }
+
+    #[test]
+    fn html_parse_never_panics() {
+        let mut random = RandomInput::new(0x5eed);
+        for _ in 0..RUNS {
+            let input = random.string(&HTML_FRAGMENTS, 40);
+            assert_no_panic(&input, || {
+                let _ = html::parse(input.clone());
+                let options = HtmlParseOptions {
+                    whitespace: WhitespaceMode::Collapse,
+                    ..HtmlParseOptions::default()
+                };
+                let _ = html::parse_with_options(input.clone(), options);
+                let _ = html::parse_with_diagnostics(input.clone());
+            });
+        }
+    }
+
+    #[test]
+    fn css_parse_never_panics() {
+        let mut random = RandomInput::new(0x5eed);
+        for _ in 0..RUNS {
+            let input = random.string(&CSS_FRAGMENTS, 40);
+            assert_no_panic(&input, || {
+                let _ = css::parse(input.clone());
+                let _ = css::parse_with_diagnostics(input.clone());
+                let _ = css::parse_value(&input);
+                let _ = css::parse_selector(&input);
+            });
+        }
+    }
+
+    // A stack overflow aborts instead of panicking, so nesting far past the
+    // depth limit is checked on its own rather than left to chance.
+    #[test]
+    fn css_deep_nesting_fails_cleanly() {
+        let nestings = [
+            ("calc(", "(", ")"),
+            ("", "calc(", ""),
+            ("", "var(--a, ", ""),
+        ];
+        for (prefix, open, suffix) in nestings {
+            let value = format!(
+                "{prefix}{}1px{}{suffix}",
+                open.repeat(10_000),
+                ")".repeat(10_000)
+            );
+            let input = format!("a {{ width: {value}; }}");
+            assert_no_panic(&input, || {
+                assert!(css::parse(input.clone()).is_err());
+                let _ = css::parse_with_diagnostics(input.clone());
+                assert!(css::parse_value(&value).is_err());
+            });
+        }
+    }
+}
@@ -1,107 +1,504 @@
-use crate::dom::{AttributeMap, Node};
-use crate::parser::Parser;
+use std::fmt;
 
-struct HTMLParser {
+use crate::dom::{AttributeMap, ElementData, Node, NodeType};
+use crate::parser::{Parser, ParserLimits, Warning};
+
+#[derive(Debug, PartialEq)]
+pub enum PartialParse {
+    Complete(Node),
+    NeedMoreInput,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HtmlParseError {
+    UnexpectedEof,
+    UnexpectedChar(String),
+    LimitExceeded(String),
+}
+
+// How text nodes treat whitespace. Whitespace between nodes is dropped in
+// every mode; `Preserve` keeps the rest as written, `Collapse` turns each run
+// into a single space and `Trim` strips it from both ends of the text.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum WhitespaceMode {
+    #[default]
+    Preserve,
+    Collapse,
+    Trim,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HtmlParseOptions {
+    pub whitespace: WhitespaceMode,
+    pub limits: ParserLimits,
+}
+
+// Receives events as a document is parsed, for tracing what the parser does.
+// Every method does nothing unless overridden.
+pub trait ParserObserver {
+    fn enter_element(&mut self, _tag_name: &str, _attributes: &AttributeMap) {}
+
+    fn exit_element(&mut self, _tag_name: &str) {}
+
+    fn text(&mut self, _text: &str) {}
+
+    fn comment(&mut self, _comment: &str) {}
+}
+
+pub struct HTMLParser {
     base: Parser,
+    limits: ParserLimits,
+    open_elements: Vec<String>,
+    preserve_tag_case: bool,
+    whitespace: WhitespaceMode,
+    diagnostics: bool,
+    warnings: Vec<Warning>,
+    observer: Option<Box<dyn ParserObserver>>,
 }
 
+type ParseResult<T> = Result<T, HtmlParseError>;
+
+impl fmt::Display for HtmlParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HtmlParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+            HtmlParseError::UnexpectedChar(message) => write!(f, "{message}"),
+            HtmlParseError::LimitExceeded(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for HtmlParseError {}
+
+// The parse methods fail with `UnexpectedEof` when the input ends before the
+// node is closed, so that a partial document can be told apart from a malformed one.
 impl HTMLParser {
-    fn new(input: String) -> Self {
+    pub fn new(input: String) -> Self {
+        HTMLParser::with_limits(input, ParserLimits::default())
+    }
+
+    pub fn with_limits(input: String, limits: ParserLimits) -> Self {
         HTMLParser {
             base: Parser::new(input),
+            limits,
+            open_elements: Vec::new(),
+            preserve_tag_case: false,
+            whitespace: WhitespaceMode::default(),
+            diagnostics: false,
+            warnings: Vec::new(),
+            observer: None,
         }
     }
 
+    pub fn set_observer(&mut self, observer: Box<dyn ParserObserver>) {
+        self.observer = Some(observer);
+    }
+
+    pub fn whitespace_mode(&mut self, mode: WhitespaceMode) {
+        self.whitespace = mode;
+    }
+
+    // Tag names are always lowercased for matching. With this enabled, the
+    // name as written is also kept so that `to_html` can write it back.
+    pub fn preserve_tag_case(&mut self, preserve: bool) {
+        self.preserve_tag_case = preserve;
+    }
+
+    pub fn feed(&mut self, chunk: &str) {
+        self.base.push_str(chunk);
+    }
+
+    pub fn parse_partial(&mut self) -> ParseResult<PartialParse> {
+        self.base.reset();
+        self.open_elements.clear();
+        match self.parse_document() {
+            Ok(node) => Ok(PartialParse::Complete(node)),
+            Err(HtmlParseError::UnexpectedEof) => Ok(PartialParse::NeedMoreInput),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn consume_char(&mut self) -> ParseResult<char> {
+        if self.base.eof() {
+            Err(HtmlParseError::UnexpectedEof)
+        } else {
+            Ok(self.base.consume_char())
+        }
+    }
+
+    fn expect_char<F>(&mut self, expected: &str, condition: F) -> ParseResult<char>
+    where
+        F: Fn(char) -> bool,
+    {
+        let c = self.base.peek_char().ok_or(HtmlParseError::UnexpectedEof)?;
+        if !condition(c) {
+            return Err(HtmlParseError::UnexpectedChar(format!(
+                "expected {expected}, found '{c}' at byte {}",
+                self.base.position()
+            )));
+        }
+        Ok(self.base.consume_char())
+    }
+
     fn parse_tag_string(&mut self) -> String {
-        self.base.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false,
-        })
+        self.base
+            .consume_while(|c| matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9'))
     }
 
-    fn parse_node(&mut self) -> Node {
+    // A document may open with a `<!DOCTYPE>` and comments before its root
+    // element. A `Node` has no room for siblings of the root, so these are
+    // skipped, except that a document of nothing but comments gives the first.
+    fn parse_document(&mut self) -> ParseResult<Node> {
+        let mut leading_comment = None;
+        loop {
+            self.base.consume_whitespace();
+            if self.at_doctype() {
+                self.base.consume_until(">");
+                if self.base.eof() {
+                    return Err(HtmlParseError::UnexpectedEof);
+                }
+                self.base.consume_char();
+            } else if self.base.start_with("<!") {
+                let comment = self.parse_comment()?;
+                leading_comment.get_or_insert(comment);
+            } else {
+                break;
+            }
+        }
+        match leading_comment {
+            Some(comment) if self.base.eof() => Ok(comment),
+            _ => self.parse_node(),
+        }
+    }
+
+    fn at_doctype(&self) -> bool {
+        let start: Option<String> = (0..9).map(|n| self.base.peek_nth(n)).collect();
+        start.is_some_and(|start| start.eq_ignore_ascii_case("<!doctype"))
+    }
+
+    fn parse_node(&mut self) -> ParseResult<Node> {
         self.base.consume_whitespace();
-        match self.base.next_char() {
-            '<' => self.parse_element(),
-            _ => self.parse_text(),
+        match self.base.peek_char() {
+            None => Err(HtmlParseError::UnexpectedEof),
+            Some('<') if self.base.start_with("<!") => self.parse_comment(),
+            Some('<') => self.parse_element(),
+            Some(_) => Ok(self.parse_text()),
+        }
+    }
+
+    // Besides `<!-- ... -->`, markup like `<![if !IE]>` is read as a comment
+    // that ends at the next `>`, as browsers do.
+    fn parse_comment(&mut self) -> ParseResult<Node> {
+        let (open, close) = match self.base.start_with("<!--") {
+            true => ("<!--", "-->"),
+            false => ("<!", ">"),
+        };
+        for _ in 0..open.len() {
+            self.base.consume_char();
+        }
+        let comment = self.base.consume_until(close);
+        if self.base.eof() {
+            return Err(HtmlParseError::UnexpectedEof);
+        }
+        for _ in 0..close.len() {
+            self.base.consume_char();
+        }
+        if let Some(observer) = self.observer.as_mut() {
+            observer.comment(&comment);
         }
+        Ok(Node::comment(comment))
     }
 
     fn parse_text(&mut self) -> Node {
-        dbg!("parse");
-        Node::text(self.base.consume_while(|c| c != '<'))
+        let text = self.base.consume_while(|c| c != '<');
+        let text = match self.whitespace {
+            WhitespaceMode::Preserve => text,
+            WhitespaceMode::Collapse => {
+                let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if text.ends_with(char::is_whitespace) {
+                    collapsed.push(' ');
+                }
+                collapsed
+            }
+            WhitespaceMode::Trim => text.trim().to_string(),
+        };
+        // Decoded after the whitespace is handled, so that an encoded space
+        // like `&#32;` is kept as written.
+        let text = decode_entities(&text);
+        if let Some(observer) = self.observer.as_mut() {
+            observer.text(&text);
+        }
+        Node::text(text)
     }
 
-    fn parse_attribute(&mut self) -> (String, String) {
+    fn parse_attribute(&mut self) -> ParseResult<(String, String)> {
         let name = self.parse_tag_string();
-        assert!(self.base.consume_char() == '=');
-        let open_quote = self.base.consume_char();
-        assert!(open_quote == '"' || open_quote == '\'');
-        let value = self.parse_tag_string();
-        let close_quote = self.base.consume_char();
-        assert!(close_quote == open_quote);
-        (name, value)
+        self.base.consume_whitespace();
+        self.expect_char("'=' after an attribute name", |c| c == '=')?;
+        self.base.consume_whitespace();
+        let open_quote = self.expect_char("a quoted attribute value", |c| c == '"' || c == '\'')?;
+        let value = decode_entities(&self.base.consume_while(|c| c != open_quote));
+        self.consume_char()?;
+        Ok((name, value))
     }
 
-    fn parse_attributes(&mut self) -> AttributeMap {
+    fn parse_attributes(&mut self) -> ParseResult<AttributeMap> {
         let mut attributes = AttributeMap::new();
 
         loop {
             self.base.consume_whitespace();
 
-            if self.base.next_char() == '>' {
-                break;
+            match self.base.peek_char() {
+                None => return Err(HtmlParseError::UnexpectedEof),
+                Some('>') => break,
+                Some(_) => {}
             }
 
-            let (name, value) = self.parse_attribute();
+            let (name, value) = self.parse_attribute()?;
             attributes.insert(name, value);
+            if attributes.len() > self.limits.max_attributes {
+                return Err(HtmlParseError::LimitExceeded(format!(
+                    "an element has more than {} attributes",
+                    self.limits.max_attributes
+                )));
+            }
         }
 
-        attributes
+        Ok(attributes)
     }
 
-    fn parse_element(&mut self) -> Node {
-        assert!(self.base.consume_char() == '<');
+    fn parse_element(&mut self) -> ParseResult<Node> {
+        self.expect_char("'<'", |c| c == '<')?;
 
         let name = self.parse_tag_string();
-        let attributes = self.parse_attributes();
+        let attributes = self.parse_attributes()?;
 
-        assert!(self.base.consume_char() == '>');
+        self.expect_char("'>'", |c| c == '>')?;
 
-        let children = self.parse_elements();
+        let lowercase_name = name.to_ascii_lowercase();
+        if let Some(observer) = self.observer.as_mut() {
+            observer.enter_element(&lowercase_name, &attributes);
+        }
+        self.open_elements.push(lowercase_name.clone());
+        if self.open_elements.len() > self.limits.max_depth {
+            return Err(HtmlParseError::LimitExceeded(format!(
+                "elements are nested deeper than {}",
+                self.limits.max_depth
+            )));
+        }
+        let mut children = self.parse_elements()?;
 
-        assert!(self
-            .base
-            .start_with(format!("</{name}>").to_string().as_str()));
+        // Close tags match regardless of case, so `<Div>` can end with `</DIV>`.
+        let close_tag = format!("</{lowercase_name}>");
         loop {
-            if self.base.consume_char() == '>' {
+            let rest: String = (0..close_tag.len())
+                .map_while(|i| self.base.peek_nth(i))
+                .collect::<String>()
+                .to_ascii_lowercase();
+            if close_tag.starts_with(&rest) {
+                if rest != close_tag {
+                    return Err(HtmlParseError::UnexpectedEof);
+                }
+                for _ in 0..close_tag.len() {
+                    self.base.consume_char();
+                }
                 break;
             }
+
+            let position = self.base.position();
+            let found = match self.peek_close_tag() {
+                Some(found) if self.diagnostics => found,
+                _ => {
+                    return Err(HtmlParseError::UnexpectedChar(format!(
+                        "expected '{close_tag}', found '{rest}' at byte {position}"
+                    )))
+                }
+            };
+            // A close tag for an enclosing element ends this one too; any other
+            // stray close tag is skipped.
+            let ancestors = &self.open_elements[..self.open_elements.len() - 1];
+            if ancestors.contains(&found) {
+                self.warn(format!(
+                    "expected '{close_tag}', found '</{found}>' at byte {position}; closed <{lowercase_name}> there"
+                ));
+                break;
+            }
+            self.warn(format!(
+                "ignored unexpected '</{found}>' at byte {position}"
+            ));
+            while self.base.consume_char() != '>' {}
+            children.extend(self.parse_elements()?);
+        }
+        self.open_elements.pop();
+        if let Some(observer) = self.observer.as_mut() {
+            observer.exit_element(&lowercase_name);
         }
 
-        Node::element(name, attributes, children)
+        let element_data = if self.preserve_tag_case {
+            ElementData::with_original_tag_name(name, attributes)
+        } else {
+            ElementData::new(name.to_ascii_lowercase(), attributes)
+        };
+        Ok(Node {
+            children,
+            node_type: NodeType::Element(element_data),
+        })
+    }
+
+    // The lowercased name of a complete close tag at the cursor, if there is one.
+    fn peek_close_tag(&self) -> Option<String> {
+        if !self.base.start_with("</") {
+            return None;
+        }
+        let name: String = (2..)
+            .map_while(|i| self.base.peek_nth(i).filter(char::is_ascii_alphanumeric))
+            .collect();
+        match self.base.peek_nth(name.len() + 2) {
+            Some('>') if !name.is_empty() => Some(name.to_ascii_lowercase()),
+            _ => None,
+        }
     }
 
-    fn parse_elements(&mut self) -> Vec<Node> {
+    // Warnings are only collected in diagnostics mode.
+    fn warn(&mut self, message: String) {
+        if self.diagnostics {
+            self.warnings.push(Warning { message });
+        }
+    }
+
+    fn parse_elements(&mut self) -> ParseResult<Vec<Node>> {
         let mut elements = Vec::<Node>::new();
         loop {
             self.base.consume_whitespace();
 
-            assert!(!self.base.eof());
+            if self.base.eof() {
+                return Err(HtmlParseError::UnexpectedEof);
+            }
             if self.base.start_with("</") {
                 break;
             }
 
-            elements.push(self.parse_node());
+            elements.push(self.parse_node()?);
+        }
+
+        Ok(elements)
+    }
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = match entity.strip_prefix('#')? {
+                hex if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok()?,
+                decimal => decimal.parse::<u32>().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// Replaces character references like `&amp;` and `&#38;` with the characters
+// they stand for. An `&` that does not start a known reference is kept as is.
+pub fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        match entity.and_then(|entity| Some((entity, decode_entity(entity)?))) {
+            Some((entity, c)) => {
+                decoded.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+// Where encoded text will be written, which decides what has to be escaped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EscapeContext {
+    Text,
+    DoubleQuotedAttribute,
+}
+
+// The inverse of `decode_entities` for serialization. Only what would be
+// misread in the context is escaped, so `'` is kept as is even in attributes.
+pub fn encode_entities(text: &str, context: EscapeContext) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => encoded.push_str("&amp;"),
+            '<' => encoded.push_str("&lt;"),
+            '>' => encoded.push_str("&gt;"),
+            '"' if context == EscapeContext::DoubleQuotedAttribute => encoded.push_str("&quot;"),
+            _ => encoded.push(c),
         }
+    }
+    encoded
+}
 
-        elements
+pub fn parse_with_limits(data: String, limits: ParserLimits) -> Result<Node, HtmlParseError> {
+    limits
+        .check_input_size(&data)
+        .map_err(HtmlParseError::LimitExceeded)?;
+    // An empty document is not an error; it is a document with no content.
+    if data.trim().is_empty() {
+        return Ok(Node::text(String::new()));
     }
+
+    let mut parser = HTMLParser::with_limits(data, limits);
+    parser.parse_document()
 }
 
-pub fn parse(data: String) -> Node {
-    let mut parser = HTMLParser::new(data.to_string());
-    parser.parse_node()
+pub fn parse_with_options(data: String, options: HtmlParseOptions) -> Result<Node, HtmlParseError> {
+    options
+        .limits
+        .check_input_size(&data)
+        .map_err(HtmlParseError::LimitExceeded)?;
+    if data.trim().is_empty() {
+        return Ok(Node::text(String::new()));
+    }
+
+    let mut parser = HTMLParser::with_limits(data, options.limits);
+    parser.whitespace_mode(options.whitespace);
+    parser.parse_document()
+}
+
+// Like `parse`, but a close tag that does not match the open element is
+// recovered from with a warning instead of failing. Warnings found before a
+// fatal error are still returned.
+pub fn parse_with_diagnostics(data: String) -> (Result<Node, HtmlParseError>, Vec<Warning>) {
+    if let Err(message) = ParserLimits::default().check_input_size(&data) {
+        return (Err(HtmlParseError::LimitExceeded(message)), Vec::new());
+    }
+    if data.trim().is_empty() {
+        return (Ok(Node::text(String::new())), Vec::new());
+    }
+
+    let mut parser = HTMLParser::new(data);
+    parser.diagnostics = true;
+    let result = parser.parse_document();
+    (result, parser.warnings)
+}
+
+pub fn parse(data: String) -> Result<Node, HtmlParseError> {
+    parse_with_limits(data, ParserLimits::default())
 }
 
 #[cfg(test)]
@@ -112,8 +509,30 @@ mod tests {
     use rstest::*;
     use speculate::speculate;
 
+    use std::cell::RefCell;
+    use std::process::Command;
+    use std::rc::Rc;
+
     use super::*;
 
+    struct Recorder(Rc<RefCell<Vec<String>>>);
+
+    impl ParserObserver for Recorder {
+        fn enter_element(&mut self, tag_name: &str, attributes: &AttributeMap) {
+            self.0
+                .borrow_mut()
+                .push(format!("enter {tag_name} {}", attributes.len()));
+        }
+
+        fn exit_element(&mut self, tag_name: &str) {
+            self.0.borrow_mut().push(format!("exit {tag_name}"));
+        }
+
+        fn text(&mut self, text: &str) {
+            self.0.borrow_mut().push(format!("text {text}"));
+        }
+    }
+
     speculate! {
         describe "'parse_element'" {
             describe "returns element without any attribute and children" {
@@ -121,7 +540,7 @@ mod tests {
                 fn test_parse_element_with_simple_element() {
                     let mut html_parser = HTMLParser::new("<div></div>".to_string());
 
-                    assert_eq!(html_parser.parse_element(), Node::element("div".to_string(), AttributeMap::new(), vec![]));
+                    assert_eq!(html_parser.parse_element().unwrap(), Node::element("div".to_string(), AttributeMap::new(), vec![]));
                 }
 
                 #[should_panic]
@@ -129,7 +548,7 @@ mod tests {
                 fn test_parse_should_panic_element_without_closing_tag() {
                     let mut html_parser = HTMLParser::new("<input>".to_string());
 
-                    html_parser.parse_element();
+                    html_parser.parse_element().unwrap();
                 }
 
                 #[should_panic]
@@ -137,7 +556,7 @@ mod tests {
                 fn test_parse_should_panic_element_with_invalid_tag() {
                     let mut html_parser = HTMLParser::new("<div />".to_string());
 
-                    html_parser.parse_element();
+                    html_parser.parse_element().unwrap();
                 }
             }
 
@@ -150,12 +569,28 @@ mod tests {
                     case(
                         "<p id=\"paragraph1\" class='ppp'></p>",
                         Node::element("p".to_string(), AttributeMap::from([("id".to_string(), "paragraph1".to_string()), ("class".to_string(), "ppp".to_string())]), Vec::<Node>::new())
+                    ),
+                    case(
+                        "<div id = \"x\" class ='y'></div>",
+                        Node::element("div".to_string(), AttributeMap::from([("id".to_string(), "x".to_string()), ("class".to_string(), "y".to_string())]), Vec::<Node>::new())
+                    ),
+                    case(
+                        "<a href=\"a.html?x=1&amp;y=2\"></a>",
+                        Node::element("a".to_string(), AttributeMap::from([("href".to_string(), "a.html?x=1&y=2".to_string())]), Vec::<Node>::new())
+                    ),
+                    case(
+                        "<a href='a.html?x=1&y=2'></a>",
+                        Node::element("a".to_string(), AttributeMap::from([("href".to_string(), "a.html?x=1&y=2".to_string())]), Vec::<Node>::new())
+                    ),
+                    case(
+                        "<p class='a b' title=\"it's\"></p>",
+                        Node::element("p".to_string(), AttributeMap::from([("class".to_string(), "a b".to_string()), ("title".to_string(), "it's".to_string())]), Vec::<Node>::new())
                     )
                 )]
                 fn test_parse_attributes_with_single_attribute(input: &str, expected: Node) {
                     let mut html_parser = HTMLParser::new(input.to_string());
 
-                    assert_eq!(html_parser.parse_element(), expected)
+                    assert_eq!(html_parser.parse_element().unwrap(), expected)
                 }
             }
 
@@ -178,6 +613,16 @@ mod tests {
                             ]))
                         ]))
                     ),
+                    case(
+                        "<div><!-- note --><p>hi</p><!----></div>",
+                        Node::element("div".to_string(), AttributeMap::new(), Vec::<Node>::from([
+                            Node::comment(" note ".to_string()),
+                            Node::element("p".to_string(), AttributeMap::new(), Vec::from([
+                                Node::text("hi".to_string())
+                            ])),
+                            Node::comment("".to_string()),
+                        ]))
+                    ),
                     case(
                         "<div><div><div><div></div></div></div></div>",
                         Node::element("div".to_string(), AttributeMap::new(), Vec::<Node>::from([
@@ -192,11 +637,321 @@ mod tests {
                 fn test_parse_element_with_children(input: &str, expected: Node) {
                     let mut html_parser = HTMLParser::new(input.to_string());
 
-                    assert_eq!(html_parser.parse_element(), expected)
+                    assert_eq!(html_parser.parse_element().unwrap(), expected)
                 }
             }
         }
 
+        describe "conditional comments are parsed as plain comments" {
+            #[rstest(input, expected,
+                case(
+                    "<div><!--[if IE]><p class=\"ie\">old</p><![endif]--><p>hi</p></div>",
+                    Vec::from([
+                        Node::comment("[if IE]><p class=\"ie\">old</p><![endif]".to_string()),
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())])),
+                    ])
+                ),
+                case(
+                    "<div><![if !IE]><p>hi</p><![endif]></div>",
+                    Vec::from([
+                        Node::comment("[if !IE]".to_string()),
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())])),
+                        Node::comment("[endif]".to_string()),
+                    ])
+                ),
+                case(
+                    "<div><!--[if !IE]><!--><p>hi</p><!--<![endif]--></div>",
+                    Vec::from([
+                        Node::comment("[if !IE]><!".to_string()),
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())])),
+                        Node::comment("<![endif]".to_string()),
+                    ])
+                ),
+            )]
+            fn test_conditional_comment(input: &str, expected: Vec<Node>) {
+                assert_eq!(parse(input.to_string()).unwrap().children, expected);
+            }
+
+            #[rstest]
+            fn test_unterminated_conditional_comment() {
+                assert_eq!(parse("<div><![if IE]".to_string()), Err(HtmlParseError::UnexpectedEof));
+            }
+        }
+
+        describe "tag names are case-insensitive" {
+            #[rstest]
+            fn test_lowercase_tag_names() {
+                let node = parse("<Div><P>hi</p></DIV>".to_string()).unwrap();
+
+                assert_eq!(node.tag_name(), Some("div"));
+                assert_eq!(node.to_html(), "<div><p>hi</p></div>");
+            }
+
+            #[rstest]
+            fn test_preserve_tag_case() {
+                let mut html_parser = HTMLParser::new("<Div><p>hi</P></div>".to_string());
+                html_parser.preserve_tag_case(true);
+                let node = match html_parser.parse_partial() {
+                    Ok(PartialParse::Complete(node)) => node,
+                    result => panic!("expected a complete document, got {result:?}"),
+                };
+
+                assert_eq!(node.tag_name(), Some("div"));
+                assert_eq!(node.to_html(), "<Div><p>hi</p></Div>");
+            }
+        }
+
+        describe "'decode_entities' replaces character references" {
+            #[rstest(input, expected,
+                case("a &amp; b", "a & b"),
+                case("&lt;p&gt; &quot;x&quot; &apos;y&apos;", "<p> \"x\" 'y'"),
+                case("&#65;&#x42;&#X43;", "ABC"),
+                case("fish & chips", "fish & chips"),
+                case("x=1&y=2;", "x=1&y=2;"),
+                case("&unknown; &#xZZ; &", "&unknown; &#xZZ; &"),
+                case("&&amp;", "&&"),
+                case("", ""),
+            )]
+            fn test_decode_entities(input: &str, expected: &str) {
+                assert_eq!(decode_entities(input), expected);
+            }
+        }
+
+        describe "'encode_entities' escapes the characters special in the context" {
+            #[rstest(input, context, expected,
+                case("a<b & \"c\"", EscapeContext::Text, "a&lt;b &amp; \"c\""),
+                case("a<b & \"c\"", EscapeContext::DoubleQuotedAttribute, "a&lt;b &amp; &quot;c&quot;"),
+                case("it's > 1", EscapeContext::DoubleQuotedAttribute, "it's &gt; 1"),
+                case("&amp;", EscapeContext::Text, "&amp;amp;"),
+                case("plain ünïcode", EscapeContext::Text, "plain ünïcode"),
+                case("", EscapeContext::Text, ""),
+            )]
+            fn test_encode_entities(input: &str, context: EscapeContext, expected: &str) {
+                assert_eq!(encode_entities(input, context), expected);
+            }
+
+            #[rstest(input,
+                case("a<b & \"c\""),
+                case("&lt; &#38; &unknown;"),
+                case("x=1&y=2;"),
+            )]
+            fn test_round_trip(input: &str) {
+                for context in [EscapeContext::Text, EscapeContext::DoubleQuotedAttribute] {
+                    assert_eq!(decode_entities(&encode_entities(input, context)), input);
+                }
+            }
+        }
+
+        describe "'parse_partial' resumes parsing after more input is fed" {
+            #[rstest(first, second, expected,
+                case(
+                    "<div id='main'><p>hel",
+                    "lo</p></div>",
+                    Node::element("div".to_string(), AttributeMap::from([("id".to_string(), "main".to_string())]), Vec::from([
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([
+                            Node::text("hello".to_string())
+                        ]))
+                    ]))
+                ),
+                case(
+                    "<div><p>hello</p></d",
+                    "iv>",
+                    Node::element("div".to_string(), AttributeMap::new(), Vec::from([
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([
+                            Node::text("hello".to_string())
+                        ]))
+                    ]))
+                ),
+                case(
+                    "<!DOCTYPE html><di",
+                    "v></div>",
+                    Node::element("div".to_string(), AttributeMap::new(), Vec::new())
+                ),
+                case(
+                    "<div cla",
+                    "ss='x'></div>",
+                    Node::element("div".to_string(), AttributeMap::from([("class".to_string(), "x".to_string())]), Vec::new())
+                ),
+                case(
+                    "<div><!-- a -",
+                    "-></div>",
+                    Node::element("div".to_string(), AttributeMap::new(), Vec::from([Node::comment(" a ".to_string())]))
+                ),
+            )]
+            fn test_parse_partial_with_two_chunks(first: &str, second: &str, expected: Node) {
+                let mut html_parser = HTMLParser::new(first.to_string());
+                assert_eq!(html_parser.parse_partial(), Ok(PartialParse::NeedMoreInput));
+
+                html_parser.feed(second);
+                assert_eq!(html_parser.parse_partial(), Ok(PartialParse::Complete(expected)));
+            }
+
+            #[rstest]
+            fn test_parse_partial_fails_with_mismatched_closing_tag() {
+                let mut html_parser = HTMLParser::new("<div></p".to_string());
+
+                assert_eq!(
+                    html_parser.parse_partial(),
+                    Err(HtmlParseError::UnexpectedChar("expected '</div>', found '</p' at byte 5".to_string()))
+                );
+            }
+        }
+
+        describe "'ParserObserver' receives parse events" {
+            #[rstest]
+            fn test_observer_events() {
+                let events = Rc::new(RefCell::new(Vec::new()));
+                let mut html_parser = HTMLParser::new("<DIV id='a'><p>hi</p><!-- c -->there</DIV>".to_string());
+                html_parser.set_observer(Box::new(Recorder(Rc::clone(&events))));
+                assert!(matches!(html_parser.parse_partial(), Ok(PartialParse::Complete(_))));
+
+                assert_eq!(*events.borrow(), Vec::from([
+                    "enter div 1", "enter p 0", "text hi", "exit p", "text there", "exit div",
+                ]));
+            }
+
+            // Runs this test again in a child process, where the child only
+            // parses, and checks that nothing reached its stderr.
+            #[rstest]
+            fn test_parse_writes_nothing_to_stderr() {
+                if std::env::var_os("BRUSER_STDERR_CHILD").is_some() {
+                    parse("<div><p>hello</p> world<!-- c --></div>".to_string()).unwrap();
+                    return;
+                }
+
+                let module = module_path!().split_once("::").unwrap().1;
+                let output = Command::new(std::env::current_exe().unwrap())
+                    .args([&format!("{module}::test_parse_writes_nothing_to_stderr"), "--exact", "--nocapture", "--quiet"])
+                    .env("BRUSER_STDERR_CHILD", "1")
+                    .output()
+                    .unwrap();
+
+                assert!(output.status.success());
+                assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+                assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+            }
+        }
+
+        describe "'parse_with_diagnostics' recovers from unexpected close tags" {
+            #[rstest(input, expected, expected_warning,
+                case(
+                    "<div><p>hi</div>",
+                    Node::element("div".to_string(), AttributeMap::new(), Vec::from([
+                        Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())]))
+                    ])),
+                    "expected '</p>', found '</div>' at byte 10; closed <p> there"
+                ),
+                case(
+                    "<div>a</span>b</div>",
+                    Node::element("div".to_string(), AttributeMap::new(), Vec::from([
+                        Node::text("a".to_string()),
+                        Node::text("b".to_string()),
+                    ])),
+                    "ignored unexpected '</span>' at byte 6"
+                ),
+            )]
+            fn test_recovered_close_tag(input: &str, expected: Node, expected_warning: &str) {
+                let (result, warnings) = parse_with_diagnostics(input.to_string());
+
+                assert_eq!(result, Ok(expected));
+                assert_eq!(warnings, Vec::from([Warning { message: expected_warning.to_string() }]));
+                assert!(parse(input.to_string()).is_err());
+            }
+
+            #[rstest]
+            fn test_no_warnings_for_valid_html() {
+                let (result, warnings) = parse_with_diagnostics("<div><p>hi</p></div>".to_string());
+
+                assert!(result.is_ok());
+                assert!(warnings.is_empty());
+            }
+
+            #[rstest]
+            fn test_input_size_limit() {
+                let max_input_bytes = ParserLimits::default().max_input_bytes;
+                let (result, warnings) = parse_with_diagnostics("a".repeat(max_input_bytes + 1));
+
+                assert_eq!(
+                    result,
+                    Err(HtmlParseError::LimitExceeded(format!("input is {} bytes, over the limit of {max_input_bytes}", max_input_bytes + 1)))
+                );
+                assert!(warnings.is_empty());
+            }
+        }
+
+        describe "'parse_with_limits' fails cleanly when a limit is exceeded" {
+            #[rstest(input, limits, expected,
+                case(
+                    "<div a='1' b='2' c='3'></div>",
+                    ParserLimits { max_attributes: 2, ..ParserLimits::default() },
+                    HtmlParseError::LimitExceeded("an element has more than 2 attributes".to_string())
+                ),
+                case(
+                    "<div><div><div></div></div></div>",
+                    ParserLimits { max_depth: 2, ..ParserLimits::default() },
+                    HtmlParseError::LimitExceeded("elements are nested deeper than 2".to_string())
+                ),
+                case(
+                    "<div>0123456789</div>",
+                    ParserLimits { max_input_bytes: 16, ..ParserLimits::default() },
+                    HtmlParseError::LimitExceeded("input is 21 bytes, over the limit of 16".to_string())
+                ),
+            )]
+            fn test_limit_exceeded(input: &str, limits: ParserLimits, expected: HtmlParseError) {
+                assert_eq!(parse_with_limits(input.to_string(), limits), Err(expected));
+            }
+
+            #[rstest(input, limits,
+                case("<div a='1' b='2'></div>", ParserLimits { max_attributes: 2, ..ParserLimits::default() }),
+                case("<div><div></div></div>", ParserLimits { max_depth: 2, ..ParserLimits::default() }),
+                case("<div>0123456</div>", ParserLimits { max_input_bytes: 18, ..ParserLimits::default() }),
+            )]
+            fn test_within_limits(input: &str, limits: ParserLimits) {
+                assert!(parse_with_limits(input.to_string(), limits).is_ok());
+            }
+        }
+
+        describe "'parse_with_options' applies the whitespace mode and limits" {
+            #[rstest(whitespace, expected,
+                case(WhitespaceMode::Preserve, "<div><p>a  b \n c  </p><b>d\te</b></div>"),
+                case(WhitespaceMode::Collapse, "<div><p>a b c </p><b>d e</b></div>"),
+                case(WhitespaceMode::Trim, "<div><p>a  b \n c</p><b>d\te</b></div>"),
+            )]
+            fn test_whitespace_mode(whitespace: WhitespaceMode, expected: &str) {
+                let node = parse_with_options(
+                    "<div>\n  <p>  a  b \n c  </p>\n  <b>d\te</b>\n</div>".to_string(),
+                    HtmlParseOptions { whitespace, ..HtmlParseOptions::default() },
+                ).unwrap();
+
+                assert_eq!(node.to_html(), expected);
+            }
+
+            #[rstest]
+            fn test_default_is_preserve() {
+                let data = "<div>\n  <p>  a  b \n c  </p>\n</div>";
+
+                assert_eq!(parse_with_options(data.to_string(), HtmlParseOptions::default()).unwrap(), parse(data.to_string()).unwrap());
+            }
+
+            #[rstest(input, limits, expected,
+                case(
+                    "<div>0123456789</div>",
+                    ParserLimits { max_input_bytes: 16, ..ParserLimits::default() },
+                    HtmlParseError::LimitExceeded("input is 21 bytes, over the limit of 16".to_string())
+                ),
+                case(
+                    "<div><div><div></div></div></div>",
+                    ParserLimits { max_depth: 2, ..ParserLimits::default() },
+                    HtmlParseError::LimitExceeded("elements are nested deeper than 2".to_string())
+                ),
+            )]
+            fn test_limit_exceeded(input: &str, limits: ParserLimits, expected: HtmlParseError) {
+                let options = HtmlParseOptions { limits, ..HtmlParseOptions::default() };
+
+                assert_eq!(parse_with_options(input.to_string(), options), Err(expected));
+            }
+        }
+
         describe "'parse' returns DOM nodes" {
             #[rstest(input, expected,
                 case(
@@ -228,17 +983,72 @@ mod tests {
                 )
             )]
             fn test_parse_valid_html(input: &str, expected: Node) {
-                assert_eq!(parse(input.to_string()), expected);
+                assert_eq!(parse(input.to_string()).unwrap(), expected);
+            }
+
+            #[rstest(input, expected,
+                case(
+                    "<!DOCTYPE html>\n<html><body><p>hi</p></body></html>",
+                    Node::element("html".to_string(), AttributeMap::new(), Vec::from([
+                        Node::element("body".to_string(), AttributeMap::new(), Vec::from([
+                            Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())])),
+                        ])),
+                    ]))
+                ),
+                case(
+                    "<!-- c --><p>hi</p>",
+                    Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())]))
+                ),
+                case(
+                    "<!doctype HTML> <!-- a --> <!-- b --> <p>hi</p>",
+                    Node::element("p".to_string(), AttributeMap::new(), Vec::from([Node::text("hi".to_string())]))
+                ),
+                case("<!-- a --> <!-- b -->", Node::comment(" a ".to_string())),
+            )]
+            fn test_parse_skips_doctype_and_leading_comments(input: &str, expected: Node) {
+                assert_eq!(parse(input.to_string()).unwrap(), expected);
             }
 
-            #[should_panic]
             #[rstest(input,
-                case("<div></div"),
-                case("<div></p>"),
-                case("<div id=class></div>"),
+                case(""),
+                case("   \n\t  "),
             )]
-            fn test_should_panic_parse_invalid_html(input: &str) {
-                parse(input.to_string());
+            fn test_parse_empty_html(input: &str) {
+                assert_eq!(parse(input.to_string()).unwrap(), Node::text(String::new()));
+            }
+
+            #[rstest(input, expected,
+                case("<div></div", HtmlParseError::UnexpectedEof),
+                case("<div></p>", HtmlParseError::UnexpectedChar("expected '</div>', found '</p>' at byte 5".to_string())),
+                case("<div id=class></div>", HtmlParseError::UnexpectedChar("expected a quoted attribute value, found 'c' at byte 8".to_string())),
+                case("<div id></div>", HtmlParseError::UnexpectedChar("expected '=' after an attribute name, found '>' at byte 7".to_string())),
+                case("</div>", HtmlParseError::UnexpectedChar("expected '=' after an attribute name, found '/' at byte 1".to_string())),
+                case("<p>日本<b x='é'>🦀</B></p", HtmlParseError::UnexpectedEof),
+                case("<!DOCTYPE html>", HtmlParseError::UnexpectedEof),
+                case("<!DOCTYPE html", HtmlParseError::UnexpectedEof),
+            )]
+            fn test_parse_invalid_html(input: &str, expected: HtmlParseError) {
+                assert_eq!(parse(input.to_string()).unwrap_err(), expected);
+            }
+
+            #[rstest]
+            fn test_parse_non_ascii_html() {
+                let node = parse("<p>日本<b x='é'>🦀</b></p>".to_string()).unwrap();
+
+                assert_eq!(node.to_html(), "<p>日本<b x=\"é\">🦀</b></p>");
+            }
+
+            #[rstest(input, text,
+                case("<p>x &amp; y</p>", "x & y"),
+                case("<p>&lt;b&gt; &#38; &#x26;amp;</p>", "<b> & &amp;"),
+            )]
+            fn test_parse_decodes_text(input: &str, text: &str) {
+                let node = parse(input.to_string()).unwrap();
+                assert_eq!(node.children, Vec::from([Node::text(text.to_string())]));
+
+                let html = node.to_html();
+                assert_eq!(parse(html.clone()).unwrap(), node);
+                assert_eq!(parse(html.clone()).unwrap().to_html(), html);
             }
         }
     }
@@ -1,9 +1,13 @@
-mod css;
-mod dom;
-mod html;
-mod parser;
-mod style;
-mod styled_dom;
+pub mod arena;
+pub mod css;
+pub mod css_tokenizer;
+pub mod dom;
+#[cfg(test)]
+mod fuzz;
+pub mod html;
+pub mod parser;
+pub mod style;
+pub mod styled_dom;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -1,22 +1,113 @@
+use std::fmt;
+
+// Bounds on the input the HTML and CSS parsers accept, so that hostile input
+// fails with an error instead of exhausting memory or the stack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParserLimits {
+    pub max_depth: usize,
+    pub max_attributes: usize,
+    pub max_input_bytes: usize,
+}
+
+// A problem the parsers recovered from in diagnostics mode. The message
+// says where in the input it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    pub message: String,
+}
+
 pub struct Parser {
     pos: usize,
     input: String,
 }
 
+// A saved cursor position. Restoring it re-reads the input from there, so a
+// parse can be tried and backed out of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint(usize);
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        ParserLimits {
+            max_depth: 512,
+            max_attributes: 1024,
+            max_input_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ParserLimits {
+    pub fn check_input_size(&self, input: &str) -> Result<(), String> {
+        if input.len() > self.max_input_bytes {
+            return Err(format!(
+                "input is {} bytes, over the limit of {}",
+                input.len(),
+                self.max_input_bytes
+            ));
+        }
+        Ok(())
+    }
+}
+
 impl Parser {
     pub fn new(input: String) -> Self {
-        Parser {
-            pos: 0,
-            input: input,
+        Parser { pos: 0, input }
+    }
+
+    pub fn push_str(&mut self, input: &str) {
+        self.input.push_str(input);
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn reset(&mut self) {
+        self.pos = 0;
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.pos)
+    }
+
+    // Input is only ever appended, so a checkpoint taken from this parser stays
+    // valid. One from another parser is clamped to the input and moved back
+    // to a char boundary rather than left to panic later.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        let mut pos = checkpoint.0.min(self.input.len());
+        while !self.input.is_char_boundary(pos) {
+            pos -= 1;
         }
+        self.pos = pos;
     }
 
+    // Panics at the end of the input. Use `peek_char` where that can happen.
     pub fn next_char(&self) -> char {
         self.input[self.pos..].chars().next().unwrap()
     }
 
+    // The char at the cursor, or None at the end of the input.
+    pub fn peek_char(&self) -> Option<char> {
+        self.input.get(self.pos..)?.chars().next()
+    }
+
+    // Looks `n` chars ahead of the cursor without consuming anything.
+    pub fn peek_nth(&self, n: usize) -> Option<char> {
+        self.input.get(self.pos..)?.chars().nth(n)
+    }
+
+    // Never panics: a needle longer than the rest of the input, or a position
+    // at or past the end, just doesn't match.
     pub fn start_with(&self, s: &str) -> bool {
-        self.input[self.pos..].starts_with(s)
+        self.input
+            .get(self.pos..)
+            .is_some_and(|rest| rest.starts_with(s))
     }
 
     pub fn eof(&self) -> bool {
@@ -24,9 +115,8 @@ impl Parser {
     }
 
     pub fn consume_char(&mut self) -> char {
-        let mut iter = self.input[self.pos..].char_indices();
-        let (_, current_char) = iter.next().unwrap();
-        self.pos += 1;
+        let current_char = self.next_char();
+        self.pos += current_char.len_utf8();
         current_char
     }
 
@@ -35,13 +125,38 @@ impl Parser {
         F: Fn(char) -> bool,
     {
         let mut result = String::new();
-        while !self.eof() && condition(self.next_char()) {
+        while self.peek_char().is_some_and(&condition) {
             result.push(self.consume_char());
         }
 
         result
     }
 
+    // Returns the run of chars satisfying `condition` from the cursor, without
+    // consuming it.
+    pub fn peek_while<F>(&self, condition: F) -> &str
+    where
+        F: Fn(char) -> bool,
+    {
+        let rest = self.input.get(self.pos..).unwrap_or_default();
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| !condition(c))
+            .map_or(rest.len(), |(i, _)| i);
+        &rest[..end]
+    }
+
+    // Consumes everything before the next `delimiter`, leaving the cursor on it.
+    // Without a delimiter, the rest of the input is consumed.
+    pub fn consume_until(&mut self, delimiter: &str) -> String {
+        let rest = &self.input[self.pos..];
+        let end = rest.find(delimiter).unwrap_or(rest.len());
+        let result = rest[..end].to_string();
+        self.pos += end;
+
+        result
+    }
+
     pub fn consume_whitespace(&mut self) {
         self.consume_while(|c| c.is_whitespace());
     }
@@ -66,29 +181,121 @@ mod tests {
             )]
             fn test_next_char(pos: usize, expected: char) {
                 let parser = Parser {
-                    pos: pos,
+                    pos,
                     input: "hello rust world!".to_string()
                 };
                 assert_eq!(parser.next_char(), expected);
             }
         }
 
+        describe "'peek_char' returns the char at the position" {
+            #[rstest(input, pos, expected,
+                case("hello", 0, Some('h')),
+                case("hello", 4, Some('o')),
+                case("hello", 5, None),
+                case("hello", 9, None),
+                case("", 0, None),
+                case("あい", 3, Some('い')),
+                case("あい", 6, None),
+            )]
+            fn test_peek_char(input: &str, pos: usize, expected: Option<char>) {
+                let parser = Parser { pos, input: input.to_string() };
+                assert_eq!(parser.peek_char(), expected);
+            }
+
+            #[rstest]
+            fn consume_while_stops_at_eof() {
+                let mut parser = Parser::new("abc".to_string());
+
+                assert_eq!(parser.consume_while(|_| true), "abc");
+                assert_eq!(parser.peek_char(), None);
+                assert_eq!(parser.consume_while(|_| true), "");
+            }
+        }
+
+        describe "'peek_nth' returns the n-th char from the position" {
+            #[rstest(pos, n, expected,
+                case(0, 0, Some('h')),
+                case(0, 4, Some('o')),
+                case(3, 1, Some('o')),
+                case(4, 1, None),
+                case(5, 0, None),
+            )]
+            fn test_peek_nth(pos: usize, n: usize, expected: Option<char>) {
+                let parser = Parser {
+                    pos,
+                    input: "hello".to_string()
+                };
+                assert_eq!(parser.peek_nth(n), expected);
+                assert_eq!(parser.position(), pos);
+            }
+        }
+
         describe "'start_with' judges if the substring of the input from the position start with a string" {
             #[rstest(pos, text, expected,
                 case(0, "hell", true),
                 case(4, "hell", false),
                 case(11, "w", true),
                 case(11, "world", true),
+                case(12, "world!!", false),
+                case(16, "!", true),
+                case(16, "!?", false),
+                case(17, "!", false),
+                case(17, "", true),
+                case(30, "!", false),
             )]
             fn test_start_with(pos: usize, text: &str, expected: bool) {
                 let parser = Parser {
-                    pos: pos,
+                    pos,
                     input: "hello rust world!".to_string()
                 };
                 assert_eq!(parser.start_with(text), expected);
             }
         }
 
+        describe "'restore' moves the cursor back to a checkpoint" {
+            #[rstest]
+            fn test_restore_rereads_the_same_input() {
+                let mut parser = Parser::new("<div id='a'>".to_string());
+                parser.consume_char();
+                let checkpoint = parser.checkpoint();
+
+                assert_eq!(parser.consume_while(|c| c.is_alphanumeric()), "div");
+                parser.consume_whitespace();
+                parser.restore(checkpoint);
+
+                assert_eq!(parser.position(), 1);
+                assert_eq!(parser.consume_while(|c| c.is_alphanumeric()), "div");
+                assert_eq!(parser.consume_until(">"), " id='a'");
+            }
+
+            #[rstest]
+            fn test_checkpoint_survives_more_input() {
+                let mut parser = Parser::new("ab".to_string());
+                parser.consume_char();
+                let checkpoint = parser.checkpoint();
+                parser.consume_char();
+                parser.push_str("cd");
+                parser.restore(checkpoint);
+
+                assert_eq!(parser.consume_until("d"), "bc");
+            }
+
+            #[rstest(input, checkpoint_input, pos, expected,
+                case("ab", "abcdef", 5, 2),
+                case("あい", "abcd", 1, 0),
+                case("あい", "abcd", 4, 3),
+            )]
+            fn test_foreign_checkpoint_is_clamped(input: &str, checkpoint_input: &str, pos: usize, expected: usize) {
+                let other = Parser { pos, input: checkpoint_input.to_string() };
+                let mut parser = Parser::new(input.to_string());
+                parser.restore(other.checkpoint());
+
+                assert_eq!(parser.position(), expected);
+                assert!(parser.eof() || parser.peek_char().is_some());
+            }
+        }
+
         describe "'eof' judges if the position is over the end of file of the input" {
             #[rstest(input, pos, expected,
                 case("hello", 4, false),
@@ -98,7 +305,7 @@ mod tests {
             )]
             fn test_eof(input: &str, pos: usize, expected: bool) {
                 let parser = Parser {
-                    pos: pos,
+                    pos,
                     input: input.to_string()
                 };
                 assert_eq!(parser.eof(), expected);
@@ -111,13 +318,15 @@ mod tests {
                     case("hello world!", 0, |c| c != ' ', "hello"),
                     case("hello world!", 3, |c| c != 'l', ""),
                     case("hello world!", 7, |c: char| c.is_alphanumeric(), "orld"),
+                    case("日本語 text", 0, |c: char| !c.is_whitespace(), "日本語"),
+                    case("café 🦀!", 3, |c| c != '!', "é 🦀"),
                 )]
                 fn test_consume_while_with_condition<F>(input: &str, pos: usize, condition: F, expected: &str)
                 where
                     F: Fn(char) -> bool
                 {
                     let mut parser = Parser {
-                        pos: pos,
+                        pos,
                         input: input.to_string()
                     };
                     assert_eq!(parser.consume_while(condition), expected);
@@ -134,7 +343,7 @@ mod tests {
                     F: Fn(char) -> bool
                 {
                     let mut parser = Parser {
-                        pos: pos,
+                        pos,
                         input: input.to_string()
                     };
                     assert_eq!(parser.consume_while(condition), expected);
@@ -142,6 +351,45 @@ mod tests {
             }
         }
 
+        describe "'peek_while' returns the run satisfying the condition without consuming it" {
+            #[rstest(input, pos, condition, expected,
+                case("hello world!", 0, |c: char| c.is_alphabetic(), "hello"),
+                case("hello world!", 6, |c: char| c != '!', "world"),
+                case("hello world!", 5, |c: char| c.is_alphabetic(), ""),
+                case("hello world!", 12, |_| true, ""),
+                case("héllo wörld", 0, |c: char| c != ' ', "héllo"),
+            )]
+            fn test_peek_while<F>(input: &str, pos: usize, condition: F, expected: &str)
+            where
+                F: Fn(char) -> bool
+            {
+                let parser = Parser {
+                    pos,
+                    input: input.to_string()
+                };
+                assert_eq!(parser.peek_while(condition), expected);
+                assert_eq!(parser.position(), pos);
+            }
+        }
+
+        describe "'consume_until' returns string before the delimiter" {
+            #[rstest(input, pos, delimiter, expected, expected_pos,
+                case("a /* b */ c", 4, "*/", " b ", 7),
+                case("<!-- x -- y -->", 4, "-->", " x -- y ", 12),
+                case("abc", 0, "a", "", 0),
+                case("no end", 0, "*/", "no end", 6),
+                case("", 0, "*/", "", 0),
+            )]
+            fn test_consume_until(input: &str, pos: usize, delimiter: &str, expected: &str, expected_pos: usize) {
+                let mut parser = Parser {
+                    pos,
+                    input: input.to_string()
+                };
+                assert_eq!(parser.consume_until(delimiter), expected);
+                assert_eq!(parser.position(), expected_pos);
+            }
+        }
+
         describe "'consume_whitespace' ignores a sequence of whitespace" {
             #[rstest]
             fn test_consume_whitespace() {
@@ -1,8 +1,58 @@
-#[derive(Debug, PartialEq)]
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::css::{self, CssParseError};
+use crate::dom::ElementData;
+use crate::styled_dom;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Combinator {
+    Descendant,
+    Child,
+    NextSibling,
+    SubsequentSibling,
+}
+
+// The `an+b` argument of `:nth-child()`. It matches the 1-based positions
+// `a*n + b` for some `n >= 0`, so `2n+1` is every odd position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NthFormula {
+    pub a: i32,
+    pub b: i32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PseudoClass {
+    Root,
+    NthChild(NthFormula),
+    NthOfType(NthFormula),
+    FirstOfType,
+    LastOfType,
+    Lang(String),
+}
+
+// Unlike a pseudo-class, which narrows down the elements a compound matches,
+// a pseudo-element selects a box generated for the element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PseudoElement {
+    Before,
+    After,
+}
+
+// A selector holds its rightmost compound, plus the combinator and selector
+// to its left when the selector is a chain like `div span`. Only the rightmost
+// compound may have a pseudo-element. `universal` records a `*` written in
+// place of the tag; it matches the same elements as no tag at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Selector {
     pub tag: Option<String>,
+    pub universal: bool,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    pub pseudo_element: Option<PseudoElement>,
+    pub combinator: Option<(Combinator, Box<Selector>)>,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -11,6 +61,8 @@ pub enum Unit {
     Percent,
     Em,
     Rem,
+    Vw,
+    Vh,
     None,
 }
 
@@ -19,25 +71,117 @@ pub struct Color {
     r: u8,
     g: u8,
     b: u8,
+    a: u8,
 }
 
+// The expression inside `calc()`. Plain numbers are sizes with `Unit::None`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CalcExpr {
+    Size(f32, Unit),
+    Add(Box<CalcExpr>, Box<CalcExpr>),
+    Sub(Box<CalcExpr>, Box<CalcExpr>),
+    Mul(Box<CalcExpr>, Box<CalcExpr>),
+    Div(Box<CalcExpr>, Box<CalcExpr>),
+}
+
+// Sizes keep the number as written, so `43%` is `Size(43.0, Unit::Percent)`.
+// `as_percent` gives the fraction, 0.43, for resolving against a base.
+// Custom properties like `--gap` keep their value as `Raw` text, which is
+// parsed when `var(--gap)` is substituted during styling.
+// A bare number like `z-index: 5` is a `Number`; `Size` always carries the
+// unit it was written with, except for `Unit::None` inside `calc()`.
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     Keyword(String),
+    Number(f32),
     Size(f32, Unit),
     Color(Color),
+    String(String),
+    Url(String),
+    CommaList(Vec<Value>),
+    Calc(CalcExpr),
+    Raw(String),
+    Var(String, Option<Box<Value>>),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    pub important: bool,
 }
 
+// A rule inside `@media` only applies while its query matches.
 #[derive(Debug, PartialEq)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    pub media: Option<MediaQuery>,
+}
+
+// The case change `text-transform` applies to text. `Capitalize` uppercases
+// the first letter of each whitespace-separated word and leaves the rest.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TextTransform {
+    #[default]
+    None,
+    Uppercase,
+    Lowercase,
+    Capitalize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorScheme {
+    #[default]
+    Light,
+    Dark,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Orientation {
+    #[default]
+    Landscape,
+    Portrait,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub width: f32,
+    pub height: f32,
+}
+
+// What media queries are evaluated against.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MediaContext {
+    pub viewport: Viewport,
+    pub color_scheme: ColorScheme,
+    pub orientation: Orientation,
+}
+
+// What the embedder configures for styling: the font size of the root before
+// any rule applies, which `rem` resolves against, and the family used where no
+// `font-family` is set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StyleContext {
+    pub root_font_size: f32,
+    pub default_font_family: String,
+}
+
+// Widths are in px; `em` and `rem` in a query are converted with the default
+// font size when it is parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaFeature {
+    MinWidth(f32),
+    MaxWidth(f32),
+    PrefersColorScheme(ColorScheme),
+    Orientation(Orientation),
+}
+
+// A media query is a list of features joined with `and`. An empty list, as
+// in `@media screen`, always matches.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MediaQuery {
+    pub features: Vec<MediaFeature>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -45,24 +189,263 @@ pub struct StyleSheet {
     pub rules: Vec<Rule>,
 }
 
-pub type Specificity = (usize, usize, usize);
+// Counts over a stylesheet, for auditing its complexity. The per-type counts
+// are of simple selectors across every compound, so `div.a.b > #x` counts one
+// tag, two classes and one id. Only an explicit `*` counts as universal, so
+// `ul > *.a` counts one and `.a` none.
+#[derive(Debug, Default, PartialEq)]
+pub struct SheetStats {
+    pub rules: usize,
+    pub selectors: usize,
+    pub id_selectors: usize,
+    pub class_selectors: usize,
+    pub tag_selectors: usize,
+    pub universal_selectors: usize,
+    pub max_specificity: Specificity,
+}
+
+// `(inline, id, class, tag)`. Selectors always have 0 for `inline`, so the
+// declarations of a `style` attribute outrank any of them.
+pub type Specificity = (usize, usize, usize, usize);
+
+pub const INLINE_SPECIFICITY: Specificity = (1, 0, 0, 0);
+
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+pub const FONT_SIZE_KEYWORDS: [&str; 10] = [
+    "xx-small",
+    "x-small",
+    "small",
+    "medium",
+    "large",
+    "x-large",
+    "xx-large",
+    "xxx-large",
+    "larger",
+    "smaller",
+];
+
+// What relative lengths resolve against: `em` uses `font_size`, `rem` uses
+// `root_font_size`, `%` uses `percent_base`, and `vw` and `vh` use `viewport`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LengthContext {
+    pub font_size: f32,
+    pub root_font_size: f32,
+    pub percent_base: f32,
+    pub viewport: Viewport,
+}
 
 impl Selector {
     pub fn new(tag: Option<String>, id: Option<String>, class: Vec<String>) -> Self {
         Selector {
-            tag: tag,
-            id: id,
-            class: class,
+            tag,
+            universal: false,
+            id,
+            class,
+            pseudo_classes: Vec::new(),
+            pseudo_element: None,
+            combinator: None,
+        }
+    }
+
+    pub fn with_universal(mut self) -> Self {
+        self.universal = true;
+        self
+    }
+
+    pub fn with_pseudo_class(mut self, pseudo_class: PseudoClass) -> Self {
+        self.pseudo_classes.push(pseudo_class);
+        self
+    }
+
+    pub fn with_pseudo_element(mut self, pseudo_element: PseudoElement) -> Self {
+        self.pseudo_element = Some(pseudo_element);
+        self
+    }
+
+    pub fn parse(input: &str) -> Result<Self, CssParseError> {
+        css::parse_selector(input)
+    }
+
+    pub fn with_combinator(self, combinator: Combinator, left: Selector) -> Self {
+        Selector {
+            combinator: Some((combinator, Box::new(left))),
+            ..self
         }
     }
 
     pub fn specificity(&self) -> Specificity {
+        let (inline, a, b, c) = match self.combinator {
+            Some((_, ref left)) => left.specificity(),
+            None => (0, 0, 0, 0),
+        };
         (
-            self.id.iter().count(),
-            self.class.len(),
-            self.tag.iter().count(),
+            inline,
+            a + self.id.iter().count(),
+            b + self.class.len() + self.pseudo_classes.len(),
+            c + self.tag.iter().count() + self.pseudo_element.iter().count(),
+        )
+    }
+}
+
+// Selectors are ordered by specificity first. Ties fall back to comparing the
+// selector parts so that distinct selectors never compare as equal.
+impl Ord for Selector {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.specificity()
+            .cmp(&other.specificity())
+            .then_with(|| self.tag.cmp(&other.tag))
+            .then_with(|| self.universal.cmp(&other.universal))
+            .then_with(|| self.id.cmp(&other.id))
+            .then_with(|| self.class.cmp(&other.class))
+            .then_with(|| self.pseudo_classes.cmp(&other.pseudo_classes))
+            .then_with(|| self.pseudo_element.cmp(&other.pseudo_element))
+            .then_with(|| self.combinator.cmp(&other.combinator))
+    }
+}
+
+impl PartialOrd for Selector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Escapes the characters the tokenizer would not read back as part of an
+// identifier, including a leading digit, which is written as a code point.
+fn escape_identifier(name: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in name.chars().enumerate() {
+        match c {
+            '0'..='9' if i == 0 => escaped.push_str(&format!("\\{:x} ", c as u32)),
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => escaped.push(c),
+            _ => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+        }
+    }
+    escaped
+}
+
+// Writes the selector the way the parser reads it: the tag first, then the
+// id and the classes, with combinators between compounds.
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.combinator {
+            Some((Combinator::Descendant, ref left)) => write!(f, "{left} ")?,
+            Some((Combinator::Child, ref left)) => write!(f, "{left} > ")?,
+            Some((Combinator::NextSibling, ref left)) => write!(f, "{left} + ")?,
+            Some((Combinator::SubsequentSibling, ref left)) => write!(f, "{left} ~ ")?,
+            None => {}
+        }
+        if let Some(ref tag) = self.tag {
+            write!(f, "{}", escape_identifier(tag))?;
+        }
+        if self.universal {
+            write!(f, "*")?;
+        }
+        if let Some(ref id) = self.id {
+            write!(f, "#{}", escape_identifier(id))?;
+        }
+        for class in &self.class {
+            write!(f, ".{}", escape_identifier(class))?;
+        }
+        for pseudo_class in &self.pseudo_classes {
+            write!(f, "{pseudo_class}")?;
+        }
+        if let Some(pseudo_element) = self.pseudo_element {
+            write!(f, "{pseudo_element}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for PseudoElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PseudoElement::Before => write!(f, "::before"),
+            PseudoElement::After => write!(f, "::after"),
+        }
+    }
+}
+
+impl NthFormula {
+    pub fn new(a: i32, b: i32) -> Self {
+        NthFormula { a, b }
+    }
+
+    pub fn matches(&self, index: usize) -> bool {
+        let offset = index as i64 - self.b as i64;
+        match self.a as i64 {
+            0 => offset == 0,
+            a => offset % a == 0 && offset / a >= 0,
+        }
+    }
+}
+
+impl fmt::Display for NthFormula {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.a {
+            0 => return write!(f, "{}", self.b),
+            1 => write!(f, "n")?,
+            -1 => write!(f, "-n")?,
+            a => write!(f, "{a}n")?,
+        }
+        match self.b {
+            0 => Ok(()),
+            b => write!(f, "{b:+}"),
+        }
+    }
+}
+
+impl fmt::Display for PseudoClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PseudoClass::Root => write!(f, ":root"),
+            PseudoClass::NthChild(formula) => write!(f, ":nth-child({formula})"),
+            PseudoClass::NthOfType(formula) => write!(f, ":nth-of-type({formula})"),
+            PseudoClass::FirstOfType => write!(f, ":first-of-type"),
+            PseudoClass::LastOfType => write!(f, ":last-of-type"),
+            PseudoClass::Lang(language) => write!(f, ":lang({language})"),
+        }
+    }
+}
+
+impl Unit {
+    pub fn is_relative(&self) -> bool {
+        matches!(
+            self,
+            Unit::Em | Unit::Rem | Unit::Percent | Unit::Vw | Unit::Vh
         )
     }
+
+    pub fn is_font_relative(&self) -> bool {
+        matches!(self, Unit::Em | Unit::Rem)
+    }
+
+    pub fn abbrev(&self) -> &'static str {
+        match self {
+            Unit::Px => "px",
+            Unit::Percent => "%",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::None => "",
+        }
+    }
+
+    // Converts `x` in this unit to px.
+    pub fn to_absolute(&self, x: f32, context: &LengthContext) -> Option<f32> {
+        match self {
+            Unit::Px | Unit::None => Some(x),
+            Unit::Percent => Some(x / 100.0 * context.percent_base),
+            Unit::Em => Some(x * context.font_size),
+            Unit::Rem => Some(x * context.root_font_size),
+            Unit::Vw => Some(x / 100.0 * context.viewport.width),
+            Unit::Vh => Some(x / 100.0 * context.viewport.height),
+        }
+    }
 }
 
 impl Value {
@@ -75,15 +458,246 @@ impl Value {
     }
 
     pub fn color(r: u8, g: u8, b: u8) -> Self {
-        Value::Color(Color { r, g, b })
+        Value::Color(Color::rgb(r, g, b))
+    }
+
+    pub fn as_keyword(&self) -> Option<&str> {
+        match self {
+            Value::Keyword(keyword) => Some(keyword),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f32> {
+        match self {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_size(&self) -> Option<(f32, &Unit)> {
+        match self {
+            Value::Size(x, unit) => Some((*x, unit)),
+            _ => None,
+        }
+    }
+
+    pub fn as_percent(&self) -> Option<f32> {
+        match self {
+            Value::Size(x, Unit::Percent) => Some(x / 100.0),
+            _ => None,
+        }
+    }
+
+    pub fn as_color(&self) -> Option<&Color> {
+        match self {
+            Value::Color(color) => Some(color),
+            _ => None,
+        }
+    }
+
+    pub fn to_px(&self, context: &LengthContext) -> Option<f32> {
+        match self {
+            Value::Size(x, unit) => unit.to_absolute(*x, context),
+            // Only zero may be written without a unit where a length goes.
+            Value::Number(x) if *x == 0.0 => Some(0.0),
+            Value::Calc(expr) => expr.to_px(context),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.abbrev())
+    }
+}
+
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Keyword(keyword) => write!(f, "{keyword}"),
+            Value::Number(x) => write!(f, "{x}"),
+            Value::Size(x, unit) => write!(f, "{x}{unit}"),
+            Value::Color(color) => write!(f, "{color}"),
+            Value::String(string) => write!(f, "{}", quote(string)),
+            Value::Url(url) => write!(f, "url({})", quote(url)),
+            Value::CommaList(values) => {
+                let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+                write!(f, "{}", values.join(", "))
+            }
+            Value::Calc(expr) => write!(f, "calc({expr})"),
+            Value::Raw(text) => write!(f, "{text}"),
+            Value::Var(name, None) => write!(f, "var({name})"),
+            Value::Var(name, Some(fallback)) => write!(f, "var({name}, {fallback})"),
+        }
+    }
+}
+
+// Writes the expression without the surrounding `calc()`. Sub-expressions are
+// parenthesized only where the precedence requires it.
+impl fmt::Display for CalcExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let is_sum = |expr: &CalcExpr| matches!(expr, CalcExpr::Add(_, _) | CalcExpr::Sub(_, _));
+        let (left, op, right, group_left, group_right) = match self {
+            CalcExpr::Size(x, unit) => return write!(f, "{x}{unit}"),
+            CalcExpr::Add(left, right) => (left, '+', right, false, false),
+            CalcExpr::Sub(left, right) => (left, '-', right, false, is_sum(right)),
+            CalcExpr::Mul(left, right) => (left, '*', right, is_sum(left), is_sum(right)),
+            CalcExpr::Div(left, right) => (
+                left,
+                '/',
+                right,
+                is_sum(left),
+                !matches!(**right, CalcExpr::Size(_, _)),
+            ),
+        };
+
+        match group_left {
+            true => write!(f, "({left})")?,
+            false => write!(f, "{left}")?,
+        }
+        write!(f, " {op} ")?;
+        match group_right {
+            true => write!(f, "({right})"),
+            false => write!(f, "{right}"),
+        }
+    }
+}
+
+impl CalcExpr {
+    // Dividing by zero leaves the expression unresolved.
+    pub fn to_px(&self, context: &LengthContext) -> Option<f32> {
+        match self {
+            CalcExpr::Size(x, unit) => unit.to_absolute(*x, context),
+            CalcExpr::Add(left, right) => Some(left.to_px(context)? + right.to_px(context)?),
+            CalcExpr::Sub(left, right) => Some(left.to_px(context)? - right.to_px(context)?),
+            CalcExpr::Mul(left, right) => Some(left.to_px(context)? * right.to_px(context)?),
+            CalcExpr::Div(left, right) => match right.to_px(context)? {
+                0.0 => None,
+                divisor => Some(left.to_px(context)? / divisor),
+            },
+        }
+    }
+}
+
+// `parent` is what the parent's lengths resolve against; `%` in a font size
+// is of the parent's font size, whatever its `percent_base`.
+// `initial_font_size` is the configured `StyleContext::root_font_size`, which
+// `medium` and an unusable value stand for.
+pub fn resolve_font_size(
+    value: Option<&Value>,
+    parent: &LengthContext,
+    initial_font_size: f32,
+) -> f32 {
+    let parent_font_size = parent.font_size;
+    let value = match value {
+        Some(value) => value,
+        None => return parent_font_size,
+    };
+
+    match value.as_keyword() {
+        Some("xx-small") => 9.0,
+        Some("x-small") => 10.0,
+        Some("small") => 13.0,
+        Some("medium") => initial_font_size,
+        Some("large") => 18.0,
+        Some("x-large") => 24.0,
+        Some("xx-large") => 32.0,
+        Some("xxx-large") => 48.0,
+        Some("larger") => parent_font_size * 1.2,
+        Some("smaller") => parent_font_size / 1.2,
+        _ => value
+            .to_px(&LengthContext {
+                percent_base: parent_font_size,
+                ..parent.clone()
+            })
+            .unwrap_or(initial_font_size),
+    }
+}
+
+impl Color {
+    pub fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Color::rgba(r, g, b, 255)
+    }
+
+    pub fn rgba(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    pub fn r(&self) -> u8 {
+        self.r
+    }
+
+    pub fn g(&self) -> u8 {
+        self.g
+    }
+
+    pub fn b(&self) -> u8 {
+        self.b
+    }
+
+    pub fn a(&self) -> u8 {
+        self.a
+    }
+
+    // Composites this color over `background` with the source-over operator.
+    // Both are straight (not premultiplied) colors, so the channels are
+    // weighted by their alpha and divided by the resulting alpha again.
+    pub fn over(&self, background: &Color) -> Color {
+        match self.a {
+            255 => return self.clone(),
+            0 => return background.clone(),
+            _ => {}
+        }
+
+        let source_alpha = self.a as f32 / 255.0;
+        let backdrop_alpha = background.a as f32 / 255.0 * (1.0 - source_alpha);
+        let alpha = source_alpha + backdrop_alpha;
+        let channel = |source: u8, backdrop: u8| {
+            ((source as f32 * source_alpha + backdrop as f32 * backdrop_alpha) / alpha).round()
+                as u8
+        };
+        Color {
+            r: channel(self.r, background.r),
+            g: channel(self.g, background.g),
+            b: channel(self.b, background.b),
+            a: (alpha * 255.0).round() as u8,
+        }
+    }
+}
+
+// Opaque colors are written as `#rrggbb`. Otherwise the alpha channel is
+// written as a fraction rounded to two decimals, as in `rgba(0, 0, 0, 0.5)`.
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.a == 255 {
+            write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+        } else {
+            let alpha = (self.a as f32 / 255.0 * 100.0).round() / 100.0;
+            write!(f, "rgba({}, {}, {}, {alpha})", self.r, self.g, self.b)
+        }
     }
 }
 
 impl Declaration {
     pub fn new(name: String, value: Value) -> Self {
         Declaration {
-            name: name,
-            value: value,
+            name,
+            value,
+            important: false,
+        }
+    }
+
+    pub fn new_important(name: String, value: Value) -> Self {
+        Declaration {
+            name,
+            value,
+            important: true,
         }
     }
 }
@@ -91,15 +705,229 @@ impl Declaration {
 impl Rule {
     pub fn new(selectors: Vec<Selector>, declarations: Vec<Declaration>) -> Self {
         Rule {
-            selectors: selectors,
-            declarations: declarations,
+            selectors,
+            declarations,
+            media: None,
+        }
+    }
+
+    pub fn with_media(self, media: MediaQuery) -> Self {
+        Rule {
+            media: Some(media),
+            ..self
+        }
+    }
+
+    pub fn applies_to(&self, context: &MediaContext) -> bool {
+        self.media
+            .as_ref()
+            .is_none_or(|media| media.matches(context))
+    }
+
+    // The declarations that take effect: when a property is declared more than
+    // once in the block, only the last one is kept, at its own position.
+    pub fn effective_declarations(&self) -> Vec<&Declaration> {
+        let mut seen = HashSet::new();
+        let mut declarations: Vec<&Declaration> = self
+            .declarations
+            .iter()
+            .rev()
+            .filter(|declaration| seen.insert(declaration.name.as_str()))
+            .collect();
+        declarations.reverse();
+        declarations
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Viewport {
+            width: 800.0,
+            height: 600.0,
+        }
+    }
+}
+
+impl TextTransform {
+    pub fn from_keyword(keyword: &str) -> Option<Self> {
+        match keyword.to_ascii_lowercase().as_str() {
+            "none" => Some(TextTransform::None),
+            "uppercase" => Some(TextTransform::Uppercase),
+            "lowercase" => Some(TextTransform::Lowercase),
+            "capitalize" => Some(TextTransform::Capitalize),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            TextTransform::None => text.to_string(),
+            TextTransform::Uppercase => text.to_uppercase(),
+            TextTransform::Lowercase => text.to_lowercase(),
+            TextTransform::Capitalize => {
+                let mut at_word_start = true;
+                let mut capitalized = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match at_word_start {
+                        true => capitalized.extend(c.to_uppercase()),
+                        false => capitalized.push(c),
+                    }
+                    at_word_start = c.is_whitespace();
+                }
+                capitalized
+            }
+        }
+    }
+}
+
+impl Default for StyleContext {
+    fn default() -> Self {
+        StyleContext {
+            root_font_size: DEFAULT_FONT_SIZE,
+            default_font_family: "serif".to_string(),
+        }
+    }
+}
+
+impl MediaContext {
+    // The orientation follows the viewport: portrait when it is taller than
+    // it is wide.
+    pub fn new(viewport: Viewport, color_scheme: ColorScheme) -> Self {
+        let orientation = match viewport.height > viewport.width {
+            true => Orientation::Portrait,
+            false => Orientation::Landscape,
+        };
+        MediaContext {
+            viewport,
+            color_scheme,
+            orientation,
+        }
+    }
+}
+
+impl MediaFeature {
+    pub fn matches(&self, context: &MediaContext) -> bool {
+        match *self {
+            MediaFeature::MinWidth(width) => context.viewport.width >= width,
+            MediaFeature::MaxWidth(width) => context.viewport.width <= width,
+            MediaFeature::PrefersColorScheme(scheme) => context.color_scheme == scheme,
+            MediaFeature::Orientation(orientation) => context.orientation == orientation,
+        }
+    }
+}
+
+impl fmt::Display for MediaFeature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaFeature::MinWidth(width) => write!(f, "(min-width: {width}px)"),
+            MediaFeature::MaxWidth(width) => write!(f, "(max-width: {width}px)"),
+            MediaFeature::PrefersColorScheme(ColorScheme::Light) => {
+                write!(f, "(prefers-color-scheme: light)")
+            }
+            MediaFeature::PrefersColorScheme(ColorScheme::Dark) => {
+                write!(f, "(prefers-color-scheme: dark)")
+            }
+            MediaFeature::Orientation(Orientation::Landscape) => {
+                write!(f, "(orientation: landscape)")
+            }
+            MediaFeature::Orientation(Orientation::Portrait) => {
+                write!(f, "(orientation: portrait)")
+            }
+        }
+    }
+}
+
+impl MediaQuery {
+    pub fn new(features: Vec<MediaFeature>) -> Self {
+        MediaQuery { features }
+    }
+
+    pub fn matches(&self, context: &MediaContext) -> bool {
+        self.features.iter().all(|feature| feature.matches(context))
+    }
+}
+
+impl fmt::Display for MediaQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.features.is_empty() {
+            return write!(f, "all");
         }
+        let features: Vec<String> = self
+            .features
+            .iter()
+            .map(|feature| feature.to_string())
+            .collect();
+        write!(f, "{}", features.join(" and "))
     }
 }
 
 impl StyleSheet {
     pub fn new(rules: Vec<Rule>) -> Self {
-        StyleSheet { rules: rules }
+        StyleSheet { rules }
+    }
+
+    // The rules that apply to the element, least specific first, with ties in
+    // source order. Only the element itself is looked at, without its
+    // ancestors or siblings, so selectors with combinators never match.
+    // `@media` rules are evaluated against the default media context.
+    pub fn rules_for(&self, element: &ElementData) -> Vec<&Rule> {
+        let media = MediaContext::default();
+        let mut rules = styled_dom::matching_rules(element.into(), &[], self, &media);
+        rules.sort_by_key(|(specificity, _)| *specificity);
+        rules.into_iter().map(|(_, rule)| rule).collect()
+    }
+
+    pub fn stats(&self) -> SheetStats {
+        let mut stats = SheetStats {
+            rules: self.rules.len(),
+            ..SheetStats::default()
+        };
+        for selector in self.rules.iter().flat_map(|rule| &rule.selectors) {
+            stats.selectors += 1;
+            stats.max_specificity = stats.max_specificity.max(selector.specificity());
+            let mut compound = Some(selector);
+            while let Some(selector) = compound {
+                stats.id_selectors += selector.id.iter().count();
+                stats.class_selectors += selector.class.len();
+                stats.tag_selectors += selector.tag.iter().count();
+                stats.universal_selectors += usize::from(selector.universal);
+                compound = selector.combinator.as_ref().map(|(_, left)| &**left);
+            }
+        }
+        stats
+    }
+
+    // Writes one rule per line, in source order, with values in their
+    // canonical form, e.g. colors as `#rrggbb`. A rule from an `@media` block
+    // gets a block of its own.
+    pub fn to_css(&self) -> String {
+        self.rules
+            .iter()
+            .map(|rule| {
+                let selectors: Vec<String> = rule
+                    .selectors
+                    .iter()
+                    .map(|selector| selector.to_string())
+                    .collect();
+                let declarations: String = rule
+                    .declarations
+                    .iter()
+                    .map(|declaration| {
+                        let important = if declaration.important {
+                            " !important"
+                        } else {
+                            ""
+                        };
+                        format!(" {}: {}{important};", declaration.name, declaration.value)
+                    })
+                    .collect();
+                let rule_text = format!("{} {{{declarations} }}", selectors.join(", "));
+                match rule.media {
+                    Some(ref media) => format!("@media {media} {{ {rule_text} }}\n"),
+                    None => format!("{rule_text}\n"),
+                }
+            })
+            .collect()
     }
 }
 
@@ -112,45 +940,561 @@ mod tests {
     use speculate::speculate;
 
     use super::*;
+    use crate::dom::AttributeMap;
 
     speculate! {
+        describe "'Selector::parse' parses a single selector" {
+            #[rstest]
+            fn test_parse_compound() {
+                let selector = Selector::parse("div.active#main").unwrap();
+
+                assert_eq!(selector, Selector::new(Some("div".to_string()), Some("main".to_string()), Vec::from(["active".to_string()])));
+                assert_eq!(selector.clone(), selector);
+            }
+
+            #[rstest]
+            fn test_parse_with_combinator() {
+                assert_eq!(
+                    Selector::parse(" ul > li ").unwrap(),
+                    Selector::new(Some("li".to_string()), None, Vec::new())
+                        .with_combinator(Combinator::Child, Selector::new(Some("ul".to_string()), None, Vec::new()))
+                );
+            }
+
+            #[rstest(input, expected,
+                case("a, b", "expected a single selector, found ',' at 1:2"),
+                case("a {", "unexpected '{' after selector at 1:3"),
+                case("", "expected a selector at 1:1"),
+                case("> a", "expected a selector at 1:1"),
+            )]
+            fn test_parse_invalid(input: &str, expected: &str) {
+                assert_eq!(Selector::parse(input).unwrap_err().to_string(), expected);
+            }
+        }
+
+        describe "'effective_declarations' keeps the last declaration of each property" {
+            #[rstest(data, expected,
+                case("a { color: red; color: blue; }", Vec::from(["color: blue"])),
+                case("a { color: red; display: block; color: blue; }", Vec::from(["display: block", "color: blue"])),
+                case("a { color: red; display: block; }", Vec::from(["color: red", "display: block"])),
+                case("a { }", Vec::new()),
+            )]
+            fn test_effective_declarations(data: &str, expected: Vec<&str>) {
+                let stylesheet = css::parse(data.to_string()).unwrap();
+                let rule = &stylesheet.rules[0];
+
+                let declarations: Vec<String> = rule
+                    .effective_declarations()
+                    .into_iter()
+                    .map(|declaration| format!("{}: {}", declaration.name, declaration.value))
+                    .collect();
+
+                assert_eq!(declarations, expected);
+            }
+
+            #[rstest]
+            fn test_keep_raw_declarations() {
+                let stylesheet = css::parse("a { color: red; color: blue; }".to_string()).unwrap();
+
+                assert_eq!(stylesheet.rules[0].declarations.len(), 2);
+            }
+        }
+
+        describe "'rules_for' returns the rules matching an element in specificity order" {
+            #[rstest]
+            fn test_rules_for() {
+                let stylesheet = css::parse("
+                    #main { color: red; }
+                    a.link { color: blue; }
+                    a { display: block; }
+                    .link.visited, p { color: gray; }
+                    b { color: green; }
+                    div a { color: black; }
+                    .link { margin: 0; }
+                ".to_string()).unwrap();
+                let element = ElementData::new("a".to_string(), AttributeMap::from([
+                    ("id".to_string(), "main".to_string()),
+                    ("class".to_string(), "link visited".to_string()),
+                ]));
+
+                let rules: Vec<String> = stylesheet
+                    .rules_for(&element)
+                    .into_iter()
+                    .map(|rule| rule.selectors[0].to_string())
+                    .collect();
+
+                assert_eq!(rules, Vec::from(["a", ".link", "a.link", ".link.visited", "#main"]));
+            }
+
+            #[rstest]
+            fn test_rules_for_without_matches() {
+                let stylesheet = css::parse("p { color: red; }".to_string()).unwrap();
+                let element = ElementData::new("a".to_string(), AttributeMap::new());
+
+                assert!(stylesheet.rules_for(&element).is_empty());
+            }
+        }
+
+        describe "'stats' counts the rules and selectors of the stylesheet" {
+            #[rstest]
+            fn test_stats() {
+                let stylesheet = css::parse("
+                    h1, h2, .title { color: red; }
+                    div.a.b > #x { color: blue; }
+                    ul li:nth-child(odd) { color: gray; }
+                    @media (max-width: 600px) { #main .nav a { display: none; } }
+                ".to_string()).unwrap();
+
+                assert_eq!(stylesheet.stats(), SheetStats {
+                    rules: 4,
+                    selectors: 6,
+                    id_selectors: 2,
+                    class_selectors: 4,
+                    tag_selectors: 6,
+                    universal_selectors: 0,
+                    max_specificity: (0, 1, 2, 1),
+                });
+            }
+
+            #[rstest(input, expected,
+                case("* { color: red; }", 1),
+                case("ul > * { color: red; }", 1),
+                case("*::before { content: 'x'; }", 1),
+                case("*.b, ul > *.c { color: red; }", 2),
+                case("::after { content: 'x'; }", 0),
+                case(".a, #x { color: red; }", 0),
+                case("p::before { content: 'x'; }", 0),
+            )]
+            fn test_universal_selectors(input: &str, expected: usize) {
+                let stylesheet = css::parse(input.to_string()).unwrap();
+
+                assert_eq!(stylesheet.stats().universal_selectors, expected);
+            }
+
+            #[rstest]
+            fn test_empty_stylesheet() {
+                assert_eq!(StyleSheet::new(Vec::new()).stats(), SheetStats::default());
+            }
+        }
+
+        describe "'to_css' serializes the stylesheet" {
+            #[rstest]
+            fn test_to_css() {
+                let stylesheet = css::parse("
+                    ul   li.item , #main>p{color:#D3A003;margin : 1.50em}
+                    p { font-family: Arial, \"Helvetica \\\"Neue\\\"\", serif; width: calc(100% - 20px); border: 1px solid #000; }
+                ".to_string()).unwrap();
+
+                assert_eq!(
+                    stylesheet.to_css(),
+                    concat!(
+                        "ul li.item, #main > p { color: #d3a003; margin: 1.5em; }\n",
+                        "p { font-family: Arial, \"Helvetica \\\"Neue\\\"\", serif; width: calc(100% - 20px); border: 1px solid #000; }\n",
+                    )
+                );
+            }
+
+            #[rstest]
+            fn test_nth_child_to_css() {
+                let stylesheet = css::parse("li:nth-child(odd), li:nth-child(-N + 3), li:nth-child(0n+4), li:nth-child(2n-1) {}".to_string()).unwrap();
+
+                assert_eq!(stylesheet.to_css(), "li:nth-child(2n+1), li:nth-child(-n+3), li:nth-child(4), li:nth-child(2n-1) { }\n");
+            }
+
+            #[rstest]
+            fn test_important_round_trip() {
+                let stylesheet = css::parse("a { color: red !important; margin: 1px 2px ! important; }".to_string()).unwrap();
+                let css_text = stylesheet.to_css();
+
+                assert_eq!(css_text, "a { color: red !important; margin: 1px 2px !important; }\n");
+                assert!(stylesheet.rules[0].declarations.iter().all(|declaration| declaration.important));
+                assert_eq!(css::parse(css_text.clone()).unwrap(), stylesheet);
+                assert_eq!(css::parse(css_text).unwrap().to_css(), stylesheet.to_css());
+            }
+
+            #[rstest]
+            fn test_escaped_selector_round_trip() {
+                let stylesheet = css::parse("#\\31 0.a\\:b { color: red; }".to_string()).unwrap();
+
+                assert_eq!(stylesheet.to_css(), "#\\31 0.a\\:b { color: red; }\n");
+                assert_eq!(css::parse(stylesheet.to_css()).unwrap(), stylesheet);
+            }
+
+            #[rstest]
+            fn test_round_trip() {
+                let stylesheet = css::parse("
+                    a#link { display: flex; color: #d3a003; }
+                    .cls, #modal > .body span { position: absolute; top: 50%; content: \"a;b\"; }
+                    p { width: calc((1em + 2px) * 3); font: italic bold 12px/1.5 Georgia, serif; }
+                    div { background-image: url(data:image/png;base64,iVBOR==); cursor: url(\"a \\\"b\\\".cur\"), auto; }
+                    li:nth-child(odd), tr:nth-child(-n+3) > td:nth-child(2) { color: red; }
+                    :root, :root > body { font-size: 20px; }
+                    h1 + p, h1 ~ ul > li + li { margin: 0; }
+                    @media screen and (max-width: 600px) and (prefers-color-scheme: dark) { a, p { color: white; } }
+                    @media all { b { margin: 0; } }
+                    :root { --gap: 1px  2px; --accent: rgb(0 0 0 / 50%); color: var(--accent); margin: var(--gap, 1em); }
+                ".to_string()).unwrap();
+
+                let css_text = stylesheet.to_css();
+
+                assert_eq!(css::parse(css_text.clone()).unwrap(), stylesheet);
+                assert_eq!(css::parse(css_text.clone()).unwrap().to_css(), css_text);
+            }
+        }
+
+        describe "'Unit' helpers classify units" {
+            #[rstest(unit, relative, font_relative, abbrev,
+                case(Unit::Px, false, false, "px"),
+                case(Unit::Percent, true, false, "%"),
+                case(Unit::Em, true, true, "em"),
+                case(Unit::Rem, true, true, "rem"),
+                case(Unit::Vw, true, false, "vw"),
+                case(Unit::Vh, true, false, "vh"),
+                case(Unit::None, false, false, ""),
+            )]
+            fn test_classify(unit: Unit, relative: bool, font_relative: bool, abbrev: &str) {
+                assert_eq!(unit.is_relative(), relative);
+                assert_eq!(unit.is_font_relative(), font_relative);
+                assert_eq!(unit.abbrev(), abbrev);
+                assert_eq!(unit.to_string(), abbrev);
+            }
+
+            #[rstest(unit, expected,
+                case(Unit::Px, Some(2.0)),
+                case(Unit::None, Some(2.0)),
+                case(Unit::Percent, Some(8.0)),
+                case(Unit::Em, Some(40.0)),
+                case(Unit::Rem, Some(32.0)),
+                case(Unit::Vw, Some(20.0)),
+                case(Unit::Vh, Some(10.0)),
+            )]
+            fn test_to_absolute(unit: Unit, expected: Option<f32>) {
+                let context = LengthContext { font_size: 20.0, root_font_size: 16.0, percent_base: 400.0, viewport: Viewport { width: 1000.0, height: 500.0 } };
+
+                assert_eq!(unit.to_absolute(2.0, &context), expected);
+            }
+        }
+
+        describe "'Value' accessors return the inner value of the matching variant" {
+            #[rstest(value, expected,
+                case(Value::keyword("block".to_string()), Some("block")),
+                case(Value::size(1.0, Unit::Px), None),
+                case(Value::color(1, 2, 3), None),
+            )]
+            fn test_as_keyword(value: Value, expected: Option<&str>) {
+                assert_eq!(value.as_keyword(), expected);
+            }
+
+            #[rstest(value, expected,
+                case(Value::Number(0.5), Some(0.5)),
+                case(Value::size(0.5, Unit::Px), None),
+                case(Value::keyword("auto".to_string()), None),
+            )]
+            fn test_as_number(value: Value, expected: Option<f32>) {
+                assert_eq!(value.as_number(), expected);
+            }
+
+            #[rstest(value, expected,
+                case(Value::size(1.5, Unit::Em), Some((1.5, &Unit::Em))),
+                case(Value::keyword("auto".to_string()), None),
+                case(Value::color(1, 2, 3), None),
+            )]
+            fn test_as_size(value: Value, expected: Option<(f32, &Unit)>) {
+                assert_eq!(value.as_size(), expected);
+            }
+
+            #[rstest(value, expected,
+                case(Value::size(43.0, Unit::Percent), Some(0.43)),
+                case(Value::size(-5.0, Unit::Percent), Some(-0.05)),
+                case(Value::size(43.0, Unit::Px), None),
+                case(Value::keyword("auto".to_string()), None),
+            )]
+            fn test_as_percent(value: Value, expected: Option<f32>) {
+                assert_eq!(value.as_percent(), expected);
+            }
+
+            #[rstest]
+            fn test_as_color() {
+                let value = Value::color(18, 52, 86);
+                let color = value.as_color().unwrap();
+
+                assert_eq!((color.r(), color.g(), color.b()), (18, 52, 86));
+                assert_eq!(Value::keyword("red".to_string()).as_color(), None);
+                assert_eq!(Value::size(1.0, Unit::Px).as_color(), None);
+            }
+        }
+
+        describe "'Color' is displayed as css text" {
+            #[rstest(color, expected,
+                case(Color::rgb(18, 52, 86), "#123456"),
+                case(Color::rgb(0, 10, 255), "#000aff"),
+                case(Color::rgba(18, 52, 86, 255), "#123456"),
+                case(Color::rgba(255, 0, 0, 128), "rgba(255, 0, 0, 0.5)"),
+                case(Color::rgba(0, 0, 0, 0), "rgba(0, 0, 0, 0)"),
+            )]
+            fn test_display_color(color: Color, expected: &str) {
+                assert_eq!(color.to_string(), expected);
+            }
+
+            #[rstest]
+            fn test_alpha() {
+                assert_eq!(Color::rgb(1, 2, 3).a(), 255);
+                assert_eq!(Color::rgba(1, 2, 3, 4).a(), 4);
+            }
+        }
+
+        describe "'MediaQuery' is evaluated against the media context" {
+            #[rstest(features, expected,
+                case(Vec::new(), true),
+                case(Vec::from([MediaFeature::PrefersColorScheme(ColorScheme::Dark)]), true),
+                case(Vec::from([MediaFeature::PrefersColorScheme(ColorScheme::Light)]), false),
+                case(Vec::from([MediaFeature::Orientation(Orientation::Portrait)]), true),
+                case(Vec::from([MediaFeature::MinWidth(400.0), MediaFeature::MaxWidth(400.0)]), true),
+                case(Vec::from([MediaFeature::MinWidth(401.0)]), false),
+                case(Vec::from([MediaFeature::PrefersColorScheme(ColorScheme::Dark), MediaFeature::Orientation(Orientation::Landscape)]), false),
+            )]
+            fn test_matches(features: Vec<MediaFeature>, expected: bool) {
+                let context = MediaContext::new(Viewport { width: 400.0, height: 700.0 }, ColorScheme::Dark);
+
+                assert_eq!(MediaQuery::new(features).matches(&context), expected);
+            }
+
+            #[rstest]
+            fn test_default_context() {
+                let context = MediaContext::default();
+
+                assert_eq!(context.viewport, Viewport { width: 800.0, height: 600.0 });
+                assert_eq!(context.color_scheme, ColorScheme::Light);
+                assert_eq!(context.orientation, Orientation::Landscape);
+            }
+        }
+
+        describe "'TextTransform' changes the case of text" {
+            #[rstest(transform, input, expected,
+                case(TextTransform::None, "Hello wORLD", "Hello wORLD"),
+                case(TextTransform::Uppercase, "Hello wORLD", "HELLO WORLD"),
+                case(TextTransform::Lowercase, "Hello wORLD", "hello world"),
+                case(TextTransform::Capitalize, "hello  wORLD\nnew-line", "Hello  WORLD\nNew-line"),
+                case(TextTransform::Uppercase, "straße", "STRASSE"),
+            )]
+            fn test_apply(transform: TextTransform, input: &str, expected: &str) {
+                assert_eq!(transform.apply(input), expected);
+            }
+        }
+
+        describe "'Color::over' composites a color over a background" {
+            #[rstest(color, background, expected,
+                case(Color::rgba(255, 0, 0, 128), Color::rgb(255, 255, 255), Color::rgb(255, 127, 127)),
+                case(Color::rgba(0, 0, 255, 51), Color::rgb(0, 0, 0), Color::rgb(0, 0, 51)),
+                case(Color::rgba(255, 0, 0, 128), Color::rgba(0, 0, 0, 0), Color::rgba(255, 0, 0, 128)),
+                case(Color::rgba(255, 0, 0, 128), Color::rgba(0, 0, 255, 128), Color::rgba(170, 0, 85, 192)),
+                case(Color::rgb(1, 2, 3), Color::rgba(255, 255, 255, 100), Color::rgb(1, 2, 3)),
+                case(Color::rgba(1, 2, 3, 0), Color::rgba(4, 5, 6, 100), Color::rgba(4, 5, 6, 100)),
+            )]
+            fn test_over(color: Color, background: Color, expected: Color) {
+                assert_eq!(color.over(&background), expected);
+            }
+        }
+
+        describe "'CalcExpr' is displayed as css text" {
+            #[rstest(expr, expected,
+                case(CalcExpr::Size(100.0, Unit::Percent), "100%"),
+                case(
+                    CalcExpr::Sub(Box::new(CalcExpr::Size(100.0, Unit::Percent)), Box::new(CalcExpr::Size(20.0, Unit::Px))),
+                    "100% - 20px"
+                ),
+                case(
+                    CalcExpr::Mul(
+                        Box::new(CalcExpr::Add(Box::new(CalcExpr::Size(1.0, Unit::Em)), Box::new(CalcExpr::Size(2.0, Unit::Px)))),
+                        Box::new(CalcExpr::Size(3.0, Unit::None)),
+                    ),
+                    "(1em + 2px) * 3"
+                ),
+                case(
+                    CalcExpr::Sub(
+                        Box::new(CalcExpr::Size(1.0, Unit::Rem)),
+                        Box::new(CalcExpr::Sub(Box::new(CalcExpr::Size(2.0, Unit::Px)), Box::new(CalcExpr::Size(3.0, Unit::Px)))),
+                    ),
+                    "1rem - (2px - 3px)"
+                ),
+                case(
+                    CalcExpr::Add(
+                        Box::new(CalcExpr::Size(1.0, Unit::Rem)),
+                        Box::new(CalcExpr::Mul(Box::new(CalcExpr::Size(2.0, Unit::None)), Box::new(CalcExpr::Size(3.0, Unit::Px)))),
+                    ),
+                    "1rem + 2 * 3px"
+                ),
+            )]
+            fn test_display_calc(expr: CalcExpr, expected: &str) {
+                assert_eq!(expr.to_string(), expected);
+            }
+        }
+
+        describe "'to_px' resolves sizes against the context" {
+            #[rstest(value, expected,
+                case(Value::size(12.0, Unit::Px), Some(12.0)),
+                case(Value::size(50.0, Unit::Percent), Some(200.0)),
+                case(Value::size(1.5, Unit::Em), Some(30.0)),
+                case(Value::size(2.0, Unit::Rem), Some(32.0)),
+                case(Value::keyword("auto".to_string()), None),
+                case(Value::Number(0.0), Some(0.0)),
+                case(Value::Number(1.5), None),
+                case(Value::Calc(CalcExpr::Sub(
+                    Box::new(CalcExpr::Size(100.0, Unit::Percent)),
+                    Box::new(CalcExpr::Size(20.0, Unit::Px)),
+                )), Some(380.0)),
+                case(Value::Calc(CalcExpr::Div(
+                    Box::new(CalcExpr::Size(1.0, Unit::Em)),
+                    Box::new(CalcExpr::Size(0.0, Unit::None)),
+                )), None),
+            )]
+            fn test_to_px(value: Value, expected: Option<f32>) {
+                let context = LengthContext { font_size: 20.0, root_font_size: 16.0, percent_base: 400.0, viewport: Viewport { width: 1000.0, height: 500.0 } };
+
+                assert_eq!(value.to_px(&context), expected);
+            }
+        }
+
+        describe "'resolve_font_size' resolves keywords and relative sizes" {
+            #[rstest(value, expected,
+                case(Value::keyword("large".to_string()), 18.0),
+                case(Value::keyword("medium".to_string()), 16.0),
+                case(Value::keyword("xx-small".to_string()), 9.0),
+                case(Value::keyword("larger".to_string()), 24.0),
+                case(Value::keyword("smaller".to_string()), 20.0 / 1.2),
+                case(Value::keyword("bigger".to_string()), 16.0),
+                case(Value::size(2.0, Unit::Em), 40.0),
+                case(Value::size(2.0, Unit::Rem), 20.0),
+                case(Value::size(50.0, Unit::Percent), 10.0),
+                case(Value::size(11.0, Unit::Px), 11.0),
+                case(Value::size(2.0, Unit::Vw), 20.0),
+            )]
+            fn test_resolve_font_size(value: Value, expected: f32) {
+                let parent = LengthContext { font_size: 20.0, root_font_size: 10.0, percent_base: 400.0, viewport: Viewport { width: 1000.0, height: 500.0 } };
+
+                assert_eq!(resolve_font_size(Some(&value), &parent, 16.0), expected);
+            }
+
+            #[rstest]
+            fn inherits_parent_font_size_when_unset() {
+                let parent = LengthContext { font_size: 20.0, root_font_size: 10.0, percent_base: 0.0, viewport: Viewport::default() };
+
+                assert_eq!(resolve_font_size(None, &parent, 16.0), 20.0);
+            }
+
+            #[rstest(value, expected,
+                case(Value::keyword("medium".to_string()), 12.0),
+                case(Value::keyword("bigger".to_string()), 12.0),
+                case(Value::keyword("large".to_string()), 18.0),
+            )]
+            fn uses_the_initial_font_size(value: Value, expected: f32) {
+                let parent = LengthContext { font_size: 20.0, root_font_size: 10.0, percent_base: 0.0, viewport: Viewport::default() };
+
+                assert_eq!(resolve_font_size(Some(&value), &parent, 12.0), expected);
+            }
+        }
+
+        describe "specificity of a combined selector is the sum of its compounds" {
+            #[rstest]
+            fn sums_compounds() {
+                let selector = Selector::new(Some("span".to_string()), None, Vec::from(["a".to_string()]))
+                    .with_combinator(Combinator::Descendant, Selector::new(Some("div".to_string()), Some("main".to_string()), Vec::new()));
+
+                assert_eq!(selector.specificity(), (0, 1, 1, 2));
+            }
+
+            #[rstest]
+            fn sums_compounds_across_sibling_combinators() {
+                let selector = Selector::parse("h1.title ~ p + span#x").unwrap();
+
+                assert_eq!(selector.specificity(), (0, 1, 1, 3));
+            }
+        }
+
+        describe "selectors are ordered by specificity" {
+            #[rstest]
+            fn sorts_selectors_in_specificity_order() {
+                let mut selectors = Vec::from([
+                    Selector::new(None, Some("id".to_string()), Vec::new()),
+                    Selector::new(Some("div".to_string()), None, Vec::from(["a".to_string(), "b".to_string()])),
+                    Selector::new(Some("div".to_string()), None, Vec::new()),
+                    Selector::new(None, None, Vec::from(["a".to_string()])),
+                ]);
+                selectors.sort();
+
+                assert_eq!(selectors, Vec::from([
+                    Selector::new(Some("div".to_string()), None, Vec::new()),
+                    Selector::new(None, None, Vec::from(["a".to_string()])),
+                    Selector::new(Some("div".to_string()), None, Vec::from(["a".to_string(), "b".to_string()])),
+                    Selector::new(None, Some("id".to_string()), Vec::new()),
+                ]));
+            }
+
+            #[rstest]
+            fn distinct_selectors_with_same_specificity_are_not_equal() {
+                let a = Selector::new(Some("h1".to_string()), None, Vec::new());
+                let b = Selector::new(Some("h2".to_string()), None, Vec::new());
+
+                assert_eq!(a.specificity(), b.specificity());
+                assert_ne!(a.cmp(&b), Ordering::Equal);
+                assert_eq!(std::collections::BTreeSet::from([a.clone(), b.clone(), a]).len(), 2);
+            }
+        }
+
         describe "calculate specificity" {
-            describe "first value represents if a id is specified" {
+            describe "first value is for inline styles" {
+                #[rstest(selector,
+                    case("a"),
+                    case("#id.class > p:nth-child(2)"),
+                )]
+                fn is_0_for_any_selector(selector: &str) {
+                    let selector = Selector::parse(selector).unwrap();
+                    assert_eq!(selector.specificity().0, 0)
+                }
+
+                #[rstest]
+                fn inline_outranks_any_selector() {
+                    let selector = Selector::parse("#a #b #c .d.e.f g h i").unwrap();
+                    assert!(INLINE_SPECIFICITY > selector.specificity())
+                }
+            }
+
+            describe "second value represents if a id is specified" {
                 #[rstest]
                 fn is_0_if_id_is_not_specified() {
                     let selector = Selector::new(None, None, Vec::new());
-                    assert_eq!(selector.specificity().0, 0)
+                    assert_eq!(selector.specificity().1, 0)
                 }
 
                 #[rstest]
                 fn is_1_if_id_is_specified() {
                     let selector = Selector::new(None, Some("id".to_string()), Vec::new());
-                    assert_eq!(selector.specificity().0, 1)
+                    assert_eq!(selector.specificity().1, 1)
                 }
             }
 
-            describe "second value represents how many classes is specified" {
+            describe "third value represents how many classes is specified" {
                 #[rstest(classes,
                     case(Vec::new()),
                     case(Vec::from(["a", "b", "c"])),
                 )]
                 fn is_length_of_classes(classes: Vec<&str>) {
                     let selector = Selector::new(None, Some("id".to_string()), classes.iter().map(|c| c.to_string()).collect());
-                    assert_eq!(selector.specificity().1, classes.len())
+                    assert_eq!(selector.specificity().2, classes.len())
                 }
             }
 
-            describe "third value represent if a tag name is specified" {
+            describe "fourth value represent if a tag name is specified" {
                 #[rstest]
                 fn is_0_if_tag_name_is_not_specified() {
                     let selector = Selector::new(None, None, Vec::new());
-                    assert_eq!(selector.specificity().2, 0)
+                    assert_eq!(selector.specificity().3, 0)
                 }
 
                 #[rstest]
                 fn is_1_if_tag_name_is_specified() {
                     let selector = Selector::new(Some("tag".to_string()), None, Vec::new());
-                    assert_eq!(selector.specificity().2, 1)
+                    assert_eq!(selector.specificity().3, 1)
                 }
             }
         }
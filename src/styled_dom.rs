@@ -1,19 +1,466 @@
 use std::collections::HashMap;
 
+use crate::css;
 use crate::dom::{ElementData, Node, NodeType};
-use crate::style::{Rule, Selector, Specificity, StyleSheet, Value};
+use crate::style::{
+    resolve_font_size, Combinator, LengthContext, MediaContext, PseudoClass, PseudoElement, Rule,
+    Selector, Specificity, StyleContext, StyleSheet, TextTransform, Value, Viewport,
+    INLINE_SPECIFICITY,
+};
 
 type MatchedRule<'a> = (Specificity, &'a Rule);
 
 type PropertyMap = HashMap<String, Value>;
 
+// An element together with the nodes that come before it under the same
+// parent, which structural pseudo-classes such as `:nth-child()` and the
+// sibling combinators look at, and whether it is the top element of the
+// tree, for `:root`. The element sits at `index` in `siblings`; an element
+// built from its preceding siblings alone has no following ones. The
+// language it inherits is only known once it is placed under its parent.
+#[derive(Clone, Copy)]
+pub struct ElementRef<'a> {
+    element_data: &'a ElementData,
+    siblings: &'a [Node],
+    index: usize,
+    is_root: bool,
+    inherited_lang: Option<&'a str>,
+}
+
+// Everything `specified_values` reads from an element. Elements with the same
+// signature get the same values, so they can share one cascade result.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ElementSignature {
+    tag: String,
+    id: Option<String>,
+    classes: Vec<String>,
+    style: Option<String>,
+    is_root: bool,
+    sibling_index: Option<usize>,
+    type_position: Option<(usize, usize)>,
+    lang: Option<String>,
+}
+
+// A cache lives for a single `style_tree` pass, so it is tied to one stylesheet.
+// When a selector has a combinator, matching also depends on the ancestors,
+// so the key becomes the signatures of the whole path from the root. Likewise
+// pseudo-classes make the sibling index, the position among siblings of the
+// same type and the effective language part of the signature. Sibling
+// combinators depend on the elements before each one on the path, which the
+// key does not cover, so with those the cache is bypassed.
+struct StyleCache {
+    entries: HashMap<Vec<ElementSignature>, PropertyMap>,
+    path: Vec<ElementSignature>,
+    include_ancestors: bool,
+    include_sibling_index: bool,
+    has_pseudo_elements: bool,
+    bypass: bool,
+}
+
+// What an element passes down to its children: its color for `currentColor`,
+// its font size for `em`, the root font size, the configured initial font
+// size, the viewport for `vw` and `vh`, its font family, its `text-transform`
+// and the custom properties in effect.
+struct InheritedValues {
+    color: Value,
+    font_size: f32,
+    root_font_size: f32,
+    initial_font_size: f32,
+    viewport: Viewport,
+    font_family: Value,
+    text_transform: TextTransform,
+    custom_properties: PropertyMap,
+}
+
 pub struct StyledNode<'a> {
     node: &'a Node,
     specified_values: PropertyMap,
     children: Vec<StyledNode<'a>>,
+    font_size: f32,
+    root_font_size: f32,
+    viewport: Viewport,
+    font_family: Value,
+    text_transform: TextTransform,
+    // Set for the box of a `::before` or `::after`, which has no DOM node of
+    // its own: `node` is then the element it belongs to.
+    pseudo_element: Option<PseudoElement>,
+    // The text as rendered where it is not the DOM's: a text node after
+    // `text-transform`, or the `content` of a pseudo-element.
+    text: Option<String>,
+}
+
+impl<'a> ElementRef<'a> {
+    pub fn new(element_data: &'a ElementData, preceding_siblings: &'a [Node]) -> Self {
+        ElementRef {
+            element_data,
+            siblings: preceding_siblings,
+            index: preceding_siblings.len(),
+            is_root: false,
+            inherited_lang: None,
+        }
+    }
+
+    // The child at `index` of a parent whose children are `siblings`, if it
+    // is an element.
+    pub fn at(siblings: &'a [Node], index: usize) -> Option<Self> {
+        Some(ElementRef {
+            element_data: siblings[index].as_element()?,
+            siblings,
+            index,
+            is_root: false,
+            inherited_lang: None,
+        })
+    }
+
+    pub fn root(element_data: &'a ElementData) -> Self {
+        ElementRef {
+            element_data,
+            siblings: &[],
+            index: 0,
+            is_root: true,
+            inherited_lang: None,
+        }
+    }
+
+    // The same element placed under `parent`, from which it inherits the
+    // language when it has no `lang` attribute of its own.
+    pub fn with_parent(mut self, parent: Option<&ElementRef<'a>>) -> Self {
+        self.inherited_lang = parent.and_then(ElementRef::lang);
+        self
+    }
+
+    pub fn element_data(&self) -> &'a ElementData {
+        self.element_data
+    }
+
+    // The language from the nearest `lang` attribute on the element or the
+    // ancestors it was placed under.
+    pub fn lang(&self) -> Option<&'a str> {
+        self.element_data
+            .get_attribute("lang")
+            .or(self.inherited_lang)
+    }
+
+    // The 1-based position among the parent's element children. Text and
+    // comments are not counted.
+    pub fn sibling_index(&self) -> usize {
+        self.preceding_siblings()
+            .iter()
+            .filter(|node| node.as_element().is_some())
+            .count()
+            + 1
+    }
+
+    // The 1-based position among the siblings with the same tag, counted
+    // from the first one, for `:nth-of-type()`.
+    pub fn type_index(&self) -> usize {
+        self.count_same_type(self.preceding_siblings()) + 1
+    }
+
+    // The same position counted from the last one, for `:last-of-type`.
+    pub fn type_index_from_end(&self) -> usize {
+        self.count_same_type(self.following_siblings()) + 1
+    }
+
+    // The element siblings before this one, nearest first, each with its own
+    // preceding siblings so that combinators can keep walking left.
+    pub fn previous_element_siblings(&self) -> impl Iterator<Item = ElementRef<'a>> + 'a {
+        let (siblings, inherited_lang) = (self.siblings, self.inherited_lang);
+        (0..self.index).rev().filter_map(move |i| {
+            let sibling = ElementRef::at(siblings, i)?;
+            Some(ElementRef {
+                inherited_lang,
+                ..sibling
+            })
+        })
+    }
+
+    fn preceding_siblings(&self) -> &'a [Node] {
+        &self.siblings[..self.index]
+    }
+
+    fn following_siblings(&self) -> &'a [Node] {
+        self.siblings.get(self.index + 1..).unwrap_or(&[])
+    }
+
+    fn count_same_type(&self, nodes: &[Node]) -> usize {
+        nodes
+            .iter()
+            .filter_map(Node::as_element)
+            .filter(|element_data| element_data.tag_name == self.element_data.tag_name)
+            .count()
+    }
+}
+
+// An element on its own, without a parent, is its parent's only child.
+impl<'a> From<&'a ElementData> for ElementRef<'a> {
+    fn from(element_data: &'a ElementData) -> Self {
+        ElementRef::new(element_data, &[])
+    }
+}
+
+fn has_sibling_combinator(selector: &Selector) -> bool {
+    match selector.combinator {
+        Some((Combinator::NextSibling | Combinator::SubsequentSibling, _)) => true,
+        Some((_, ref left)) => has_sibling_combinator(left),
+        None => false,
+    }
+}
+
+fn has_pseudo_classes(selector: &Selector) -> bool {
+    !selector.pseudo_classes.is_empty()
+        || selector
+            .combinator
+            .as_ref()
+            .is_some_and(|(_, left)| has_pseudo_classes(left))
+}
+
+impl ElementSignature {
+    fn new(element_data: &ElementData) -> Self {
+        let mut classes: Vec<String> = element_data
+            .classes()
+            .into_iter()
+            .map(|class| class.to_string())
+            .collect();
+        classes.sort();
+
+        ElementSignature {
+            tag: element_data.tag_name.clone(),
+            id: element_data.id().cloned(),
+            classes,
+            style: element_data.get_attribute("style").map(str::to_string),
+            is_root: false,
+            sibling_index: None,
+            type_position: None,
+            lang: None,
+        }
+    }
+}
+
+impl StyleCache {
+    fn new(stylesheet: &StyleSheet) -> Self {
+        StyleCache {
+            entries: HashMap::new(),
+            path: Vec::new(),
+            include_ancestors: stylesheet
+                .rules
+                .iter()
+                .flat_map(|rule| &rule.selectors)
+                .any(|selector| selector.combinator.is_some()),
+            include_sibling_index: stylesheet
+                .rules
+                .iter()
+                .flat_map(|rule| &rule.selectors)
+                .any(has_pseudo_classes),
+            has_pseudo_elements: stylesheet
+                .rules
+                .iter()
+                .flat_map(|rule| &rule.selectors)
+                .any(|selector| selector.pseudo_element.is_some()),
+            bypass: stylesheet
+                .rules
+                .iter()
+                .flat_map(|rule| &rule.selectors)
+                .any(has_sibling_combinator),
+        }
+    }
+
+    fn key(&self) -> Vec<ElementSignature> {
+        if self.include_ancestors {
+            self.path.clone()
+        } else {
+            self.path.last().cloned().into_iter().collect()
+        }
+    }
+}
+
+impl InheritedValues {
+    // What the root element inherits, before any rule applies.
+    fn initial(context: &StyleContext, viewport: Viewport) -> Self {
+        InheritedValues {
+            color: Value::color(0, 0, 0),
+            font_size: context.root_font_size,
+            root_font_size: context.root_font_size,
+            initial_font_size: context.root_font_size,
+            viewport,
+            font_family: Value::keyword(context.default_font_family.clone()),
+            text_transform: TextTransform::None,
+            custom_properties: PropertyMap::new(),
+        }
+    }
+}
+
+impl Default for InheritedValues {
+    fn default() -> Self {
+        InheritedValues::initial(&StyleContext::default(), Viewport::default())
+    }
+}
+
+impl<'a> StyledNode<'a> {
+    // For a generated pseudo-element, the element it belongs to.
+    pub fn node(&self) -> &'a Node {
+        self.node
+    }
+
+    pub fn value(&self, name: &str) -> Option<&Value> {
+        self.specified_values.get(name)
+    }
+
+    pub fn children(&self) -> &[StyledNode<'a>] {
+        &self.children
+    }
+
+    // Walks this node and its descendants in pre-order, with a stack instead
+    // of recursion.
+    pub fn iter(&self) -> impl Iterator<Item = &StyledNode<'a>> + '_ {
+        let mut stack = Vec::from([self]);
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev());
+            Some(node)
+        })
+    }
+
+    // The computed font size in pixels, resolved once during styling from the
+    // parent's, so `em` lengths do not need to walk the ancestors.
+    pub fn font_size_px(&self) -> f32 {
+        self.font_size
+    }
+
+    // The font size of the root element, which `rem` lengths resolve against.
+    pub fn root_font_size(&self) -> f32 {
+        self.root_font_size
+    }
+
+    // The `font-family` in effect, inherited from the parent or else the
+    // default family of the `StyleContext`.
+    pub fn font_family(&self) -> &Value {
+        &self.font_family
+    }
+
+    pub fn text_transform(&self) -> TextTransform {
+        self.text_transform
+    }
+
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        self.pseudo_element
+    }
+
+    // The content of a text node or a pseudo-element as it is rendered, with
+    // `text-transform` applied. None for elements and comments.
+    pub fn text(&self) -> Option<&str> {
+        match self.node.node_type {
+            _ if self.pseudo_element.is_some() => self.text.as_deref(),
+            NodeType::Text(ref text) => Some(self.text.as_deref().unwrap_or(text)),
+            _ => None,
+        }
+    }
+
+    // What lengths on this node resolve against, given the size that `%`
+    // refers to for the property at hand.
+    pub fn length_context(&self, percent_base: f32) -> LengthContext {
+        LengthContext {
+            font_size: self.font_size,
+            root_font_size: self.root_font_size,
+            percent_base,
+            viewport: self.viewport,
+        }
+    }
+
+    fn element_data(&self) -> Option<&'a ElementData> {
+        match self.pseudo_element {
+            Some(_) => None,
+            None => self.node.as_element(),
+        }
+    }
+
+    pub fn tag_name(&self) -> Option<&'a str> {
+        self.element_data()
+            .map(|element_data| element_data.tag_name.as_str())
+    }
+
+    pub fn id(&self) -> Option<&'a str> {
+        self.element_data()
+            .and_then(|element_data| element_data.id())
+            .map(|id| id.as_str())
+    }
+
+    pub fn has_class(&self, class: &str) -> bool {
+        self.element_data()
+            .is_some_and(|element_data| element_data.classes().contains(class))
+    }
+}
+
+#[cfg(feature = "serde")]
+fn value_to_json(value: &Value) -> serde_json::Value {
+    use serde_json::json;
+
+    match value {
+        Value::Keyword(keyword) => json!({ "keyword": keyword }),
+        Value::Number(x) => json!({ "number": x }),
+        Value::Size(x, unit) => json!({ "size": x, "unit": unit.to_string() }),
+        Value::Color(color) => json!({ "color": color.to_string() }),
+        Value::String(string) => json!({ "string": string }),
+        Value::Url(url) => json!({ "url": url }),
+        Value::CommaList(values) => {
+            json!({ "list": values.iter().map(value_to_json).collect::<Vec<_>>() })
+        }
+        Value::Calc(expr) => json!({ "calc": expr.to_string() }),
+        Value::Raw(text) => json!({ "raw": text }),
+        Value::Var(..) => json!({ "var": value.to_string() }),
+    }
 }
 
-fn matches_selector(element_data: &ElementData, selector: &Selector) -> bool {
+#[cfg(feature = "serde")]
+fn styled_node_to_json(styled_node: &StyledNode) -> serde_json::Value {
+    use serde_json::json;
+
+    let children: Vec<_> = styled_node
+        .children
+        .iter()
+        .map(styled_node_to_json)
+        .collect();
+    if let Some(pseudo_element) = styled_node.pseudo_element {
+        let values: serde_json::Map<_, _> = styled_node
+            .specified_values
+            .iter()
+            .map(|(name, value)| (name.clone(), value_to_json(value)))
+            .collect();
+        return json!({
+            "pseudo_element": pseudo_element.to_string(),
+            "text": styled_node.text,
+            "values": values,
+            "children": children,
+        });
+    }
+    match styled_node.node.node_type {
+        NodeType::Text(ref text) => json!({ "text": text, "children": children }),
+        NodeType::Comment(ref comment) => json!({ "comment": comment, "children": children }),
+        NodeType::Element(ref element_data) => {
+            let values: serde_json::Map<_, _> = styled_node
+                .specified_values
+                .iter()
+                .map(|(name, value)| (name.clone(), value_to_json(value)))
+                .collect();
+            json!({
+                "tag": element_data.tag_name,
+                "attributes": element_data.attributes(),
+                "values": values,
+                "children": children,
+            })
+        }
+    }
+}
+
+// Serializes the styled tree for debugging. Object keys are sorted, so the
+// output of two runs can be diffed.
+#[cfg(feature = "serde")]
+pub fn to_json(styled_node: &StyledNode) -> String {
+    serde_json::to_string_pretty(&styled_node_to_json(styled_node)).unwrap()
+}
+
+fn matches_selector(element: ElementRef, selector: &Selector) -> bool {
+    let element_data = element.element_data();
     if selector.tag.iter().any(|tag| element_data.tag_name != *tag) {
         return false;
     }
@@ -22,66 +469,462 @@ fn matches_selector(element_data: &ElementData, selector: &Selector) -> bool {
         return false;
     }
 
-    let element_classes = element_data.classes();
-    if selector
-        .class
+    // Collecting the classes allocates a set, so it is skipped for the common
+    // selectors that have none, like a bare tag.
+    if !selector.class.is_empty() {
+        let element_classes = element_data.classes();
+        if selector
+            .class
+            .iter()
+            .any(|class| !element_classes.contains(&**class))
+        {
+            return false;
+        }
+    }
+
+    selector
+        .pseudo_classes
         .iter()
-        .any(|class| !element_classes.contains(&**class))
-    {
+        .all(|pseudo_class| match pseudo_class {
+            PseudoClass::Root => element.is_root,
+            PseudoClass::NthChild(formula) => formula.matches(element.sibling_index()),
+            PseudoClass::NthOfType(formula) => formula.matches(element.type_index()),
+            PseudoClass::FirstOfType => element.type_index() == 1,
+            PseudoClass::LastOfType => element.type_index_from_end() == 1,
+            PseudoClass::Lang(range) => {
+                element.lang().is_some_and(|lang| lang_matches(lang, range))
+            }
+        })
+}
+
+// A language range matches the language itself and its subtags, so `en`
+// matches `en-US` but not `eng`. Both are compared ignoring case.
+fn lang_matches(lang: &str, range: &str) -> bool {
+    lang.get(..range.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(range))
+        && matches!(lang.as_bytes().get(range.len()), None | Some(b'-'))
+}
+
+// `ancestors` is the chain of elements from the root down to the parent of
+// `element`. Compounds are matched right to left against that chain.
+// A selector with a pseudo-element matches a generated box rather than the
+// element, so it never matches here.
+pub fn matches(selector: &Selector, element: ElementRef, ancestors: &[ElementRef]) -> bool {
+    selector.pseudo_element.is_none() && matches_originating_element(selector, element, ancestors)
+}
+
+// Matches as if the selector had no pseudo-element, i.e. against the element
+// a pseudo-element belongs to.
+fn matches_originating_element(
+    selector: &Selector,
+    element: ElementRef,
+    ancestors: &[ElementRef],
+) -> bool {
+    if !matches_selector(element, selector) {
         return false;
     }
 
-    true
+    match selector.combinator {
+        None => true,
+        Some((Combinator::Child, ref left)) => match ancestors.split_last() {
+            Some((parent, rest)) => matches(left, *parent, rest),
+            None => false,
+        },
+        Some((Combinator::Descendant, ref left)) => (0..ancestors.len())
+            .rev()
+            .any(|i| matches(left, ancestors[i], &ancestors[..i])),
+        Some((Combinator::NextSibling, ref left)) => element
+            .previous_element_siblings()
+            .next()
+            .is_some_and(|sibling| matches(left, sibling, ancestors)),
+        Some((Combinator::SubsequentSibling, ref left)) => element
+            .previous_element_siblings()
+            .any(|sibling| matches(left, sibling, ancestors)),
+    }
+}
+
+fn collect_matches<'a>(
+    siblings: &'a [Node],
+    index: usize,
+    selector: &Selector,
+    ancestors: &mut Vec<ElementRef<'a>>,
+    found: &mut Vec<&'a Node>,
+) {
+    let Some(element) = ElementRef::at(siblings, index) else {
+        return;
+    };
+    let element = element.with_parent(ancestors.last());
+    let node = &siblings[index];
+    if matches(selector, element, ancestors) {
+        found.push(node);
+    }
+
+    ancestors.push(element);
+    for i in 0..node.children.len() {
+        collect_matches(&node.children, i, selector, ancestors, found);
+    }
+    ancestors.pop();
+}
+
+// Returns the descendant elements matching the selector in document order.
+// Like the DOM method, the root itself is never returned, but it does count
+// as an ancestor for combinators.
+pub fn query_selector_all<'a>(root: &'a Node, selector: &Selector) -> Vec<&'a Node> {
+    let mut found = Vec::new();
+    let mut ancestors = Vec::from_iter(root.as_element().map(ElementRef::root));
+    for i in 0..root.children.len() {
+        collect_matches(&root.children, i, selector, &mut ancestors, &mut found);
+    }
+    found
 }
 
-fn matching_rule<'a>(element_data: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
+// A rule matches once however many of its selectors match, and it is ordered
+// by the most specific of those, as if each selector had its own rule.
+// With a pseudo-element, only the selectors for it count.
+fn matching_rule<'a>(
+    element: ElementRef,
+    ancestors: &[ElementRef],
+    rule: &'a Rule,
+    pseudo_element: Option<PseudoElement>,
+) -> Option<MatchedRule<'a>> {
     rule.selectors
         .iter()
-        .find(|selector| matches_selector(element_data, *selector))
-        .map(|selector| (selector.specificity(), rule))
+        .filter(|selector| selector.pseudo_element == pseudo_element)
+        .filter(|selector| matches_originating_element(selector, element, ancestors))
+        .map(|selector| selector.specificity())
+        .max()
+        .map(|specificity| (specificity, rule))
 }
 
-fn matching_rules<'a>(
-    element_data: &ElementData,
+fn rules_matching<'a>(
+    element: ElementRef,
+    ancestors: &[ElementRef],
     stylesheet: &'a StyleSheet,
+    media: &MediaContext,
+    pseudo_element: Option<PseudoElement>,
 ) -> Vec<MatchedRule<'a>> {
     stylesheet
         .rules
         .iter()
-        .filter_map(|rule| matching_rule(element_data, rule))
+        .filter(|rule| rule.applies_to(media))
+        .filter_map(|rule| matching_rule(element, ancestors, rule, pseudo_element))
         .collect()
 }
 
-fn specified_values(element_data: &ElementData, stylesheet: &StyleSheet) -> PropertyMap {
-    let mut property_map = PropertyMap::new();
-
-    let mut rules = matching_rules(element_data, stylesheet);
-    rules.sort_by(|(a, _), (b, _)| a.cmp(b));
+pub(crate) fn matching_rules<'a>(
+    element: ElementRef,
+    ancestors: &[ElementRef],
+    stylesheet: &'a StyleSheet,
+    media: &MediaContext,
+) -> Vec<MatchedRule<'a>> {
+    rules_matching(element, ancestors, stylesheet, media, None)
+}
 
+// Applies the declarations of the rules, least specific first, so that the
+// most specific one wins.
+fn cascade(mut rules: Vec<MatchedRule>) -> PropertyMap {
+    let mut property_map = PropertyMap::new();
+    rules.sort_by_key(|(specificity, _)| *specificity);
     for (_, rule) in rules {
         for declaration in &rule.declarations {
             property_map.insert(declaration.name.clone(), declaration.value.clone());
         }
     }
-
     property_map
 }
 
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a StyleSheet) -> StyledNode<'a> {
+fn specified_values(
+    element: ElementRef,
+    ancestors: &[ElementRef],
+    stylesheet: &StyleSheet,
+    media: &MediaContext,
+) -> PropertyMap {
+    // A `style` attribute that does not parse is ignored.
+    let inline_rule = element
+        .element_data()
+        .get_attribute("style")
+        .and_then(|style| css::parse_inline_style(style).ok())
+        .map(|declarations| Rule::new(Vec::new(), declarations));
+
+    let mut rules = matching_rules(element, ancestors, stylesheet, media);
+    rules.extend(inline_rule.as_ref().map(|rule| (INLINE_SPECIFICITY, rule)));
+    cascade(rules)
+}
+
+fn is_current_color(value: &Value) -> bool {
+    value
+        .as_keyword()
+        .is_some_and(|keyword| keyword.eq_ignore_ascii_case("currentColor"))
+}
+
+// Replaces `currentColor` with the element's color, which is inherited when
+// the element does not set one. Returns that color for the children.
+fn resolve_current_color(values: &mut PropertyMap, parent_color: &Value) -> Value {
+    let color = match values.get("color") {
+        Some(color) if !is_current_color(color) => color.clone(),
+        _ => parent_color.clone(),
+    };
+    for value in values.values_mut() {
+        if is_current_color(value) {
+            *value = color.clone();
+        }
+    }
+    color
+}
+
+// Bounds how many `var()` are followed through custom properties, so that a
+// cycle like `--a: var(--b); --b: var(--a)` ends instead of recursing forever.
+const MAX_VAR_DEPTH: usize = 32;
+
+// Substitutes `var()` with the parsed value of the custom property, or with
+// the fallback when the property is not defined or does not parse. Returns
+// None when neither gives a value.
+fn resolve_var(value: &Value, custom_properties: &PropertyMap, depth: usize) -> Option<Value> {
+    if depth > MAX_VAR_DEPTH {
+        return None;
+    }
+
+    match value {
+        Value::Var(name, fallback) => {
+            let substituted = match custom_properties.get(name) {
+                Some(Value::Raw(text)) => css::parse_value(text).ok(),
+                _ => None,
+            };
+            let value = substituted.or_else(|| fallback.as_deref().cloned())?;
+            resolve_var(&value, custom_properties, depth + 1)
+        }
+        Value::CommaList(values) => values
+            .iter()
+            .map(|value| resolve_var(value, custom_properties, depth))
+            .collect::<Option<_>>()
+            .map(Value::CommaList),
+        value => Some(value.clone()),
+    }
+}
+
+// Custom properties are inherited, and the element's own declarations win
+// over its parent's. A declaration whose `var()` cannot be resolved is
+// dropped, as if it had not been written.
+fn resolve_custom_properties(
+    values: &mut PropertyMap,
+    parent_properties: &PropertyMap,
+) -> PropertyMap {
+    let mut custom_properties = parent_properties.clone();
+    for (name, value) in values.iter() {
+        if name.starts_with("--") {
+            custom_properties.insert(name.clone(), value.clone());
+        }
+    }
+    for (name, value) in parent_properties {
+        values.entry(name.clone()).or_insert_with(|| value.clone());
+    }
+
+    values.retain(|_, value| match resolve_var(value, &custom_properties, 0) {
+        Some(resolved) => {
+            *value = resolved;
+            true
+        }
+        None => false,
+    });
+    custom_properties
+}
+
+// `element` is `root` as seen from its parent, or None for text and comments.
+fn style_node<'a>(
+    root: &'a Node,
+    element: Option<ElementRef<'a>>,
+    stylesheet: &'a StyleSheet,
+    media: &MediaContext,
+    ancestors: &mut Vec<ElementRef<'a>>,
+    cache: &mut StyleCache,
+    inherited: &InheritedValues,
+) -> StyledNode<'a> {
+    let is_root = ancestors.is_empty();
+    let element = element.map(|element| element.with_parent(ancestors.last()));
+
+    let mut specified_values = match element {
+        Some(element) => {
+            let mut signature = ElementSignature::new(element.element_data());
+            signature.is_root = element.is_root;
+            if cache.include_sibling_index {
+                signature.sibling_index = Some(element.sibling_index());
+                signature.type_position =
+                    Some((element.type_index(), element.type_index_from_end()));
+                signature.lang = element.lang().map(str::to_string);
+            }
+            cache.path.push(signature);
+            if cache.bypass {
+                specified_values(element, ancestors, stylesheet, media)
+            } else {
+                let key = cache.key();
+                cache
+                    .entries
+                    .entry(key)
+                    .or_insert_with(|| specified_values(element, ancestors, stylesheet, media))
+                    .clone()
+            }
+        }
+        None => HashMap::new(),
+    };
+    let inherited = inherit(&mut specified_values, inherited, is_root);
+    let text = match root.node_type {
+        NodeType::Text(ref text) if inherited.text_transform != TextTransform::None => {
+            Some(inherited.text_transform.apply(text))
+        }
+        _ => None,
+    };
+
+    let generate = |pseudo_element| {
+        let element = element.filter(|_| cache.has_pseudo_elements)?;
+        let rules = rules_matching(element, ancestors, stylesheet, media, Some(pseudo_element));
+        style_pseudo_element(root, pseudo_element, cascade(rules), &inherited)
+    };
+    let before = generate(PseudoElement::Before);
+    let after = generate(PseudoElement::After);
+
+    ancestors.extend(element);
+    let mut children = Vec::from_iter(before);
+    children.extend(root.children.iter().enumerate().map(|(i, child)| {
+        style_node(
+            child,
+            ElementRef::at(&root.children, i),
+            stylesheet,
+            media,
+            ancestors,
+            cache,
+            &inherited,
+        )
+    }));
+    children.extend(after);
+    if element.is_some() {
+        ancestors.pop();
+        cache.path.pop();
+    }
+
     StyledNode {
         node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref element_data) => specified_values(element_data, stylesheet),
-            NodeType::Text(_) => HashMap::new(),
-        },
-        children: root
-            .children
-            .iter()
-            .map(|child| style_tree(child, stylesheet))
-            .collect(),
+        specified_values,
+        children,
+        font_size: inherited.font_size,
+        root_font_size: inherited.root_font_size,
+        viewport: inherited.viewport,
+        font_family: inherited.font_family,
+        text_transform: inherited.text_transform,
+        pseudo_element: None,
+        text,
+    }
+}
+
+// Resolves what depends on the parent in the specified values, and returns
+// what the node passes down in turn.
+fn inherit(
+    specified_values: &mut PropertyMap,
+    inherited: &InheritedValues,
+    is_root: bool,
+) -> InheritedValues {
+    let custom_properties =
+        resolve_custom_properties(specified_values, &inherited.custom_properties);
+    let color = resolve_current_color(specified_values, &inherited.color);
+    // At the root, `rem` in its own font size refers to the initial size.
+    let parent = LengthContext {
+        font_size: inherited.font_size,
+        root_font_size: inherited.root_font_size,
+        percent_base: inherited.font_size,
+        viewport: inherited.viewport,
+    };
+    let font_size = resolve_font_size(
+        specified_values.get("font-size"),
+        &parent,
+        inherited.initial_font_size,
+    );
+    let root_font_size = match is_root {
+        true => font_size,
+        false => inherited.root_font_size,
+    };
+    let font_family = specified_values
+        .get("font-family")
+        .unwrap_or(&inherited.font_family)
+        .clone();
+    // An unsupported keyword is ignored like any invalid value, leaving the
+    // inherited transform.
+    let text_transform = specified_values
+        .get("text-transform")
+        .and_then(Value::as_keyword)
+        .and_then(TextTransform::from_keyword)
+        .unwrap_or(inherited.text_transform);
+    InheritedValues {
+        color,
+        font_size,
+        root_font_size,
+        initial_font_size: inherited.initial_font_size,
+        viewport: inherited.viewport,
+        font_family,
+        text_transform,
+        custom_properties,
     }
 }
 
+// A pseudo-element only generates a box when its `content` is a string;
+// other values, like `none`, are not supported and generate nothing. The box
+// inherits from the element it belongs to, like a first or last child.
+fn style_pseudo_element<'a>(
+    element: &'a Node,
+    pseudo_element: PseudoElement,
+    mut specified_values: PropertyMap,
+    parent: &InheritedValues,
+) -> Option<StyledNode<'a>> {
+    let inherited = inherit(&mut specified_values, parent, false);
+    let text = match specified_values.get("content") {
+        Some(Value::String(content)) => inherited.text_transform.apply(content),
+        _ => return None,
+    };
+    Some(StyledNode {
+        node: element,
+        specified_values,
+        children: Vec::new(),
+        font_size: inherited.font_size,
+        root_font_size: inherited.root_font_size,
+        viewport: inherited.viewport,
+        font_family: inherited.font_family,
+        text_transform: inherited.text_transform,
+        pseudo_element: Some(pseudo_element),
+        text: Some(text),
+    })
+}
+
+pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a StyleSheet) -> StyledNode<'a> {
+    style_tree_for_media(root, stylesheet, &MediaContext::default())
+}
+
+// Styles the tree with only the `@media` rules whose query matches `media`.
+pub fn style_tree_for_media<'a>(
+    root: &'a Node,
+    stylesheet: &'a StyleSheet,
+    media: &MediaContext,
+) -> StyledNode<'a> {
+    style_tree_with_context(root, stylesheet, media, &StyleContext::default())
+}
+
+// Like `style_tree_for_media`, with the root font size and default font
+// family taken from `context` instead of the 16px and `serif` defaults.
+pub fn style_tree_with_context<'a>(
+    root: &'a Node,
+    stylesheet: &'a StyleSheet,
+    media: &MediaContext,
+    context: &StyleContext,
+) -> StyledNode<'a> {
+    let mut cache = StyleCache::new(stylesheet);
+    style_node(
+        root,
+        root.as_element().map(ElementRef::root),
+        stylesheet,
+        media,
+        &mut Vec::new(),
+        &mut cache,
+        &InheritedValues::initial(context, media.viewport),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     extern crate rstest;
@@ -92,8 +935,9 @@ mod tests {
 
     use super::*;
     use crate::css;
-    use crate::dom::AttributeMap;
-    use crate::style::Declaration;
+    use crate::dom::{AttributeMap, Element, NodeType};
+    use crate::html;
+    use crate::style::{ColorScheme, Declaration, Unit};
 
     speculate! {
         describe "'matches_selector'" {
@@ -103,7 +947,7 @@ mod tests {
                     let element_data = ElementData::new("hoge".to_string(), AttributeMap::new());
                     let selector = Selector::new(Some("hoge".to_string()), None, Vec::new());
 
-                    assert!(matches_selector(&element_data, &selector));
+                    assert!(matches_selector((&element_data).into(), &selector));
                 }
 
                 #[rstest]
@@ -111,7 +955,7 @@ mod tests {
                     let element_data = ElementData::new("div".to_string(), AttributeMap::new());
                     let selector = Selector::new(Some("image".to_string()), None, Vec::new());
 
-                    assert!(!matches_selector(&element_data, &selector));
+                    assert!(!matches_selector((&element_data).into(), &selector));
                 }
             }
 
@@ -121,7 +965,7 @@ mod tests {
                     let element_data = ElementData::new("button".to_string(), AttributeMap::new());
                     let selector = Selector::new(None, Some("submit".to_string()), Vec::new());
 
-                    assert!(!matches_selector(&element_data, &selector));
+                    assert!(!matches_selector((&element_data).into(), &selector));
                 }
 
                 #[rstest]
@@ -129,7 +973,7 @@ mod tests {
                     let element_data = ElementData::new("button".to_string(), AttributeMap::from([("id".to_string(), "delete".to_string())]));
                     let selector = Selector::new(None, Some("submit".to_string()), Vec::new());
 
-                    assert!(!matches_selector(&element_data, &selector));
+                    assert!(!matches_selector((&element_data).into(), &selector));
                 }
 
                 #[rstest]
@@ -137,7 +981,7 @@ mod tests {
                     let element_data = ElementData::new("button".to_string(), AttributeMap::from([("id".to_string(), "submit".to_string())]));
                     let selector = Selector::new(None, Some("submit".to_string()), Vec::new());
 
-                    assert!(matches_selector(&element_data, &selector));
+                    assert!(matches_selector((&element_data).into(), &selector));
                 }
             }
 
@@ -148,7 +992,7 @@ mod tests {
                         let element_data = ElementData::new("button".to_string(), AttributeMap::new());
                         let selector = Selector::new(None, None, Vec::from(["cls".to_string()]));
 
-                        assert!(!matches_selector(&element_data, &selector))
+                        assert!(!matches_selector((&element_data).into(), &selector))
                     }
                 }
 
@@ -164,7 +1008,7 @@ mod tests {
                             ElementData::new("button".to_string(), AttributeMap::from([("class".to_string(), element_classes.to_string())]));
                         let selector = Selector::new(None, None, selector_classes.iter().map(|c| c.to_string()).collect());
 
-                        assert!(matches_selector(&element_data, &selector))
+                        assert!(matches_selector((&element_data).into(), &selector))
                     }
 
                     #[rstest(element_classes, selector_classes,
@@ -176,13 +1020,707 @@ mod tests {
                             ElementData::new("button".to_string(), AttributeMap::from([("class".to_string(), element_classes.to_string())]));
                         let selector = Selector::new(None, None, selector_classes.iter().map(|c| c.to_string()).collect());
 
-                        assert!(!matches_selector(&element_data, &selector))
+                        assert!(!matches_selector((&element_data).into(), &selector))
                     }
 
                 }
             }
         }
 
+        describe "'matches_selector' without classes in the selector" {
+            #[rstest(selector, class, expected,
+                case(Selector::new(Some("li".to_string()), None, Vec::new()), Some("a b"), true),
+                case(Selector::new(Some("li".to_string()), None, Vec::new()), None, true),
+                case(Selector::new(Some("ul".to_string()), None, Vec::new()), Some("a b"), false),
+                case(Selector::new(None, Some("x".to_string()), Vec::new()), Some("a b"), true),
+                case(Selector::new(Some("li".to_string()), Some("y".to_string()), Vec::new()), Some("a"), false),
+                case(Selector::new(None, None, Vec::new()), Some("a"), true),
+            )]
+            fn ignores_the_classes_of_the_element(selector: Selector, class: Option<&str>, expected: bool) {
+                let mut attributes = AttributeMap::from([("id".to_string(), "x".to_string())]);
+                if let Some(class) = class {
+                    attributes.insert("class".to_string(), class.to_string());
+                }
+                let element_data = ElementData::new("li".to_string(), attributes);
+
+                assert_eq!(matches_selector((&element_data).into(), &selector), expected);
+            }
+        }
+
+        describe "'matches' evaluates combinators against the ancestors" {
+            #[rstest(selector, expected,
+                case("span", true),
+                case("div span", true),
+                case("section span", true),
+                case("section div p span", true),
+                case("body span", false),
+                case("p > span", true),
+                case("div > span", false),
+                case("section > div > p > span", true),
+                case("section > p > span", false),
+                case("section p > span", true),
+                case("div > p span", true),
+            )]
+            fn test_matches_with_ancestors(selector: &str, expected: bool) {
+                let section = ElementData::new("section".to_string(), AttributeMap::new());
+                let div = ElementData::new("div".to_string(), AttributeMap::new());
+                let p = ElementData::new("p".to_string(), AttributeMap::new());
+                let span = ElementData::new("span".to_string(), AttributeMap::new());
+                let stylesheet = css::parse(format!("{selector} {{}}")).unwrap();
+
+                assert_eq!(matches(&stylesheet.rules[0].selectors[0], (&span).into(), &[(&section).into(), (&div).into(), (&p).into()]), expected);
+            }
+
+            #[rstest]
+            fn test_child_combinator_without_parent() {
+                let span = ElementData::new("span".to_string(), AttributeMap::new());
+                let stylesheet = css::parse("p > span {}".to_string()).unwrap();
+
+                assert!(!matches(&stylesheet.rules[0].selectors[0], (&span).into(), &[]));
+            }
+        }
+
+        describe "'matches' checks ':nth-child()' against the sibling index" {
+            #[rstest(selector, expected,
+                case("li:nth-child(odd)", Vec::from(["1", "3", "5"])),
+                case("li:nth-child(2n)", Vec::from(["2", "4"])),
+                case("li:nth-child(3)", Vec::from(["3"])),
+                case("li:nth-child(n)", Vec::from(["1", "2", "3", "4", "5"])),
+                case("li:nth-child(-n+2)", Vec::from(["1", "2"])),
+                case("li:nth-child(n+4)", Vec::from(["4", "5"])),
+                case("li:nth-child(3n - 1)", Vec::from(["2", "5"])),
+                case("ul > :nth-child(even)", Vec::from(["2", "4"])),
+                case(":nth-child(1) li", Vec::from(["1", "2", "3", "4", "5"])),
+            )]
+            fn test_nth_child(selector: &str, expected: Vec<&str>) {
+                let root = html::parse("<div><ul><li id='1'></li>text<li id='2'></li><!-- x --><li id='3'></li><li id='4'></li><li id='5'></li></ul></div>".to_string()).unwrap();
+
+                let ids: Vec<&str> = query_selector_all(&root, &Selector::parse(selector).unwrap())
+                    .into_iter()
+                    .map(|node| node.get_attribute("id").unwrap())
+                    .collect();
+
+                assert_eq!(ids, expected);
+            }
+
+            #[rstest]
+            fn style_tree_does_not_share_values_across_positions() {
+                let stylesheet = css::parse("li:nth-child(odd) { color: red; }".to_string()).unwrap();
+                let root = Element::new("ul")
+                    .child(Element::new("li").build())
+                    .child(Element::new("li").build())
+                    .child(Element::new("li").build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].value("color"), Some(&Value::Keyword("red".to_string())));
+                assert_eq!(styled.children()[1].value("color"), None);
+                assert_eq!(styled.children()[2].value("color"), Some(&Value::Keyword("red".to_string())));
+            }
+        }
+
+        describe "'matches' checks ':lang()' against the nearest 'lang' attribute" {
+            #[rstest(selector, expected,
+                case(":lang(en)", Vec::from(["body", "a", "c", "d"])),
+                case("p:lang(EN)", Vec::from(["a", "c", "d"])),
+                case(":lang(en-us)", Vec::from(["body", "a", "c", "d"])),
+                case(":lang(en-GB)", Vec::new()),
+                case(":lang(fr)", Vec::from(["b", "e"])),
+                case(":lang(e)", Vec::new()),
+                case(":lang(fr) + :lang(en)", Vec::from(["c"])),
+                case(":lang(fr) ~ p:lang(en)", Vec::from(["c", "d"])),
+            )]
+            fn test_lang(selector: &str, expected: Vec<&str>) {
+                let root = html::parse("<html lang='en-US'><body id='body'><p id='a'></p><p id='b' lang='fr'><span id='e'></span></p><p id='c'></p><p id='d'></p></body></html>".to_string()).unwrap();
+
+                let ids: Vec<&str> = query_selector_all(&root, &Selector::parse(selector).unwrap())
+                    .into_iter()
+                    .map(|node| node.get_attribute("id").unwrap())
+                    .collect();
+
+                assert_eq!(ids, expected);
+            }
+
+            #[rstest]
+            fn style_tree_does_not_share_values_across_languages() {
+                let stylesheet = css::parse("p:lang(en) { color: red; }".to_string()).unwrap();
+                let root = html::parse("<html lang='en-US'><div lang='fr'><p></p></div><div><p></p></div></html>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].children()[0].value("color"), None);
+                assert_eq!(styled.children()[1].children()[0].value("color"), Some(&Value::Keyword("red".to_string())));
+            }
+        }
+
+        describe "'matches' checks the '-of-type' pseudo-classes among siblings with the same tag" {
+            #[rstest(selector, expected,
+                case("p:first-of-type", Vec::from(["b"])),
+                case("p:last-of-type", Vec::from(["e"])),
+                case("div:first-of-type", Vec::from(["a"])),
+                case("div:last-of-type", Vec::from(["d"])),
+                case("p:nth-of-type(2)", Vec::from(["c"])),
+                case("p:nth-of-type(odd)", Vec::from(["b", "e"])),
+                case(":nth-of-type(2)", Vec::from(["c", "d"])),
+                case("p:nth-child(1)", Vec::new()),
+            )]
+            fn test_of_type(selector: &str, expected: Vec<&str>) {
+                let root = html::parse("<section><div id='a'></div><p id='b'></p>text<p id='c'></p><div id='d'></div><p id='e'></p></section>".to_string()).unwrap();
+
+                let ids: Vec<&str> = query_selector_all(&root, &Selector::parse(selector).unwrap())
+                    .into_iter()
+                    .map(|node| node.get_attribute("id").unwrap())
+                    .collect();
+
+                assert_eq!(ids, expected);
+            }
+
+            #[rstest]
+            fn style_tree_does_not_share_values_across_type_positions() {
+                let stylesheet = css::parse("p:last-of-type { color: red; }".to_string()).unwrap();
+                let root = html::parse("<div><p></p><p></p><span></span></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].value("color"), None);
+                assert_eq!(styled.children()[1].value("color"), Some(&Value::Keyword("red".to_string())));
+            }
+        }
+
+        describe "'matches' evaluates sibling combinators against the preceding siblings" {
+            #[rstest(selector, expected,
+                case("h1 + p", Vec::from(["a"])),
+                case("h1 ~ p", Vec::from(["a", "b", "d"])),
+                case("h2 + p", Vec::from(["d"])),
+                case("h1 ~ h2 + p", Vec::from(["d"])),
+                case("p + p", Vec::from(["b"])),
+                case("h1 + h2", Vec::new()),
+                case("section > h1 ~ p", Vec::from(["a", "b", "d"])),
+                case("h1 ~ section", Vec::new()),
+                case("p ~ span p", Vec::from(["e"])),
+                case("h2 + span p", Vec::new()),
+            )]
+            fn test_sibling_combinators(selector: &str, expected: Vec<&str>) {
+                let root = html::parse("<div><section><h1></h1>text<p id='a'></p><!-- x --><p id='b'></p><h2></h2><p id='d'></p><span><p id='e'></p></span></section><p id='f'></p></div>".to_string()).unwrap();
+
+                let ids: Vec<&str> = query_selector_all(&root, &Selector::parse(selector).unwrap())
+                    .into_iter()
+                    .filter_map(|node| node.get_attribute("id"))
+                    .collect();
+
+                assert_eq!(ids, expected);
+            }
+
+            #[rstest]
+            fn style_tree_does_not_share_values_across_siblings() {
+                let stylesheet = css::parse("h1 + li { color: red; } h1 ~ li { display: block; }".to_string()).unwrap();
+                let root = Element::new("ul")
+                    .child(Element::new("li").build())
+                    .child(Element::new("h1").build())
+                    .child(Element::new("li").build())
+                    .child(Element::new("li").build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].value("display"), None);
+                assert_eq!(styled.children()[2].value("color"), Some(&Value::Keyword("red".to_string())));
+                assert_eq!(styled.children()[3].value("color"), None);
+                assert_eq!(styled.children()[3].value("display"), Some(&Value::Keyword("block".to_string())));
+            }
+        }
+
+        describe "'matches' checks ':root' against the top element" {
+            #[rstest(selector, expected,
+                case(":root", Vec::from(["html"])),
+                case("div:root", Vec::new()),
+                case(":root > body", Vec::from(["body"])),
+                case(":root div", Vec::from(["div"])),
+            )]
+            fn test_root(selector: &str, expected: Vec<&str>) {
+                let stylesheet = css::parse(format!("{selector} {{ color: red; }}")).unwrap();
+                let root = html::parse("<html><body><div><p></p></div></body></html>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                let matched: Vec<&str> = styled
+                    .iter()
+                    .filter(|node| node.value("color").is_some())
+                    .map(|node| node.tag_name().unwrap())
+                    .collect();
+
+                assert_eq!(matched, expected);
+            }
+
+            #[rstest]
+            fn does_not_share_values_with_a_lookalike() {
+                let stylesheet = css::parse(":root { color: red; }".to_string()).unwrap();
+                let root = Element::new("div").child(Element::new("div").build()).build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.value("color"), Some(&Value::Keyword("red".to_string())));
+                assert_eq!(styled.children()[0].value("color"), None);
+            }
+        }
+
+        describe "'style_tree_with_context' starts from the configured root font size and family" {
+            #[rstest(stylesheet_data, expected_root, expected_width,
+                case("", 10.0, 20.0),
+                case(":root { font-size: 2em; }", 20.0, 40.0),
+                case(":root { font-size: 2rem; }", 20.0, 40.0),
+                case(":root { font-size: 12px; }", 12.0, 24.0),
+            )]
+            fn test_root_font_size(stylesheet_data: &str, expected_root: f32, expected_width: f32) {
+                let stylesheet = css::parse(format!("{stylesheet_data} p {{ width: 2rem; font-size: 2rem; }}")).unwrap();
+                let root = html::parse("<html><p>text</p></html>".to_string()).unwrap();
+                let context = StyleContext { root_font_size: 10.0, default_font_family: "serif".to_string() };
+                let styled = style_tree_with_context(&root, &stylesheet, &MediaContext::default(), &context);
+                let paragraph = &styled.children()[0];
+                let width = paragraph.value("width").unwrap().to_px(&paragraph.length_context(0.0));
+
+                assert_eq!(styled.root_font_size(), expected_root);
+                assert_eq!(paragraph.font_size_px(), expected_width);
+                assert_eq!(width, Some(expected_width));
+            }
+
+            #[rstest(stylesheet_data,
+                case("p { font-size: medium; }"),
+                case(":root { font-size: 2em; } p { font-size: medium; }"),
+                case("p { font-size: bogus; }"),
+            )]
+            fn resolves_medium_to_the_configured_size(stylesheet_data: &str) {
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                let root = html::parse("<html><p>text</p></html>".to_string()).unwrap();
+                let context = StyleContext { root_font_size: 10.0, default_font_family: "serif".to_string() };
+                let styled = style_tree_with_context(&root, &stylesheet, &MediaContext::default(), &context);
+
+                assert_eq!(styled.children()[0].font_size_px(), 10.0);
+            }
+
+            #[rstest]
+            fn inherits_the_default_font_family() {
+                let stylesheet = css::parse("em { font-family: monospace; }".to_string()).unwrap();
+                let root = html::parse("<p>a<em>b</em></p>".to_string()).unwrap();
+                let context = StyleContext { root_font_size: 16.0, default_font_family: "Helvetica".to_string() };
+                let styled = style_tree_with_context(&root, &stylesheet, &MediaContext::default(), &context);
+
+                assert_eq!(styled.font_family(), &Value::keyword("Helvetica".to_string()));
+                assert_eq!(styled.children()[0].font_family(), &Value::keyword("Helvetica".to_string()));
+                assert_eq!(styled.children()[1].font_family(), &Value::keyword("monospace".to_string()));
+                assert_eq!(styled.children()[1].children()[0].font_family(), &Value::keyword("monospace".to_string()));
+                assert_eq!(style_tree(&root, &stylesheet).font_family(), &Value::keyword("serif".to_string()));
+            }
+        }
+
+        describe "'style_tree' generates boxes for '::before' and '::after'" {
+            #[rstest]
+            fn test_before_and_after() {
+                let stylesheet = css::parse(".x { color: red; font-size: 10px; } .x::before { content: \">\"; font-size: 2em; } .x::after { content: \"<\"; } p::after { content: \"!\"; }".to_string()).unwrap();
+                let root = html::parse("<div><span class='x'>hi<em>there</em></span></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let span = &styled.children()[0];
+                let children = span.children();
+
+                assert_eq!(children.len(), 4);
+                assert_eq!(children[0].pseudo_element(), Some(PseudoElement::Before));
+                assert_eq!(children[0].text(), Some(">"));
+                assert_eq!(children[0].tag_name(), None);
+                assert_eq!(children[0].value("color"), None);
+                assert_eq!(children[0].font_size_px(), 20.0);
+                assert_eq!(children[1].text(), Some("hi"));
+                assert_eq!(children[2].tag_name(), Some("em"));
+                assert_eq!(children[3].pseudo_element(), Some(PseudoElement::After));
+                assert_eq!(children[3].text(), Some("<"));
+                assert_eq!(children[3].font_size_px(), 10.0);
+                assert_eq!(span.value("content"), None);
+                assert_eq!(span.pseudo_element(), None);
+                assert_eq!(styled.children().len(), 1);
+            }
+
+            #[rstest(stylesheet_data, expected,
+                case(".x::before { content: \">\"; }", Some(">")),
+                case(".x::before { content: none; }", None),
+                case(".x::before { color: red; }", None),
+                case(".x::before { content: \">\"; } .x::before { content: none; }", None),
+                case("#y::before { content: \"a\"; } .x::before { content: \"b\"; }", Some("a")),
+                case(".x::before { content: \"a\"; text-transform: uppercase; }", Some("A")),
+                case(".x::before { content: var(--mark); } .x { --mark: \"*\"; }", Some("*")),
+                case(".x { content: \">\"; }", None),
+                case("div .x::before, .x::after { content: \">\"; }", Some(">")),
+            )]
+            fn test_content(stylesheet_data: &str, expected: Option<&str>) {
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                let root = html::parse("<div><p class='x' id='y'>text</p></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let before = styled.children()[0]
+                    .children()
+                    .iter()
+                    .find(|child| child.pseudo_element() == Some(PseudoElement::Before));
+
+                assert_eq!(before.and_then(StyledNode::text), expected);
+            }
+
+            #[rstest]
+            fn does_not_match_the_element_itself() {
+                let stylesheet = css::parse("p::before { color: red; content: \">\"; }".to_string()).unwrap();
+                let root = html::parse("<div><p>text</p></div>".to_string()).unwrap();
+
+                assert!(query_selector_all(&root, &Selector::parse("p::before").unwrap()).is_empty());
+                assert_eq!(style_tree(&root, &stylesheet).children()[0].value("color"), None);
+            }
+        }
+
+        describe "'style_tree' applies the inherited 'text-transform' to text nodes" {
+            #[rstest(stylesheet_data, expected,
+                case("", "Hello world"),
+                case("div { text-transform: uppercase; }", "HELLO WORLD"),
+                case("p { text-transform: lowercase; }", "hello world"),
+                case("div { text-transform: capitalize; }", "Hello World"),
+                case("div { text-transform: uppercase; } p { text-transform: none; }", "Hello world"),
+                case("div { text-transform: uppercase; } p { text-transform: bogus; }", "HELLO WORLD"),
+            )]
+            fn test_text_transform(stylesheet_data: &str, expected: &str) {
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                let root = html::parse("<div><p>Hello world</p></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let text = &styled.children()[0].children()[0];
+
+                assert_eq!(text.text(), Some(expected));
+                assert_eq!(styled.text(), None);
+            }
+
+            #[rstest]
+            fn keeps_the_dom_unchanged() {
+                let stylesheet = css::parse("p { text-transform: uppercase; }".to_string()).unwrap();
+                let root = html::parse("<p>hi</p>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.text_transform(), TextTransform::Uppercase);
+                assert_eq!(styled.children()[0].text(), Some("HI"));
+                assert_eq!(root.to_html(), "<p>hi</p>");
+            }
+        }
+
+        describe "'style_tree' resolves the root font size from the root element" {
+            #[rstest(stylesheet_data, expected,
+                case("", 16.0),
+                case(":root { font-size: 20px; }", 20.0),
+                case(":root { font-size: 2em; }", 32.0),
+                case("p { font-size: 20px; }", 16.0),
+            )]
+            fn test_root_font_size(stylesheet_data: &str, expected: f32) {
+                let stylesheet = css::parse(format!("{stylesheet_data} p {{ width: 2rem; }}")).unwrap();
+                let root = html::parse("<html><body><p>text</p></body></html>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let paragraph = &styled.children()[0].children()[0];
+                let context = LengthContext {
+                    font_size: 16.0,
+                    root_font_size: paragraph.root_font_size(),
+                    percent_base: 0.0,
+                    viewport: Viewport::default(),
+                };
+
+                assert_eq!(styled.root_font_size(), expected);
+                assert_eq!(paragraph.children()[0].root_font_size(), expected);
+                assert_eq!(paragraph.value("width").unwrap().to_px(&context), Some(expected * 2.0));
+            }
+        }
+
+        describe "'style_tree' stores the computed font size on each node" {
+            #[rstest]
+            fn chains_em_through_nested_nodes() {
+                let stylesheet = css::parse(":root { font-size: 20px; } section { font-size: 1.5em; } p { font-size: 2em; } span { font-size: 2rem; } em { font-size: 0.5em; }".to_string()).unwrap();
+                let root = html::parse("<html><section><p>a<em>b</em></p><div>c</div></section><span>d</span></html>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let section = &styled.children()[0];
+                let paragraph = &section.children()[0];
+
+                assert_eq!(styled.font_size_px(), 20.0);
+                assert_eq!(section.font_size_px(), 30.0);
+                assert_eq!(paragraph.font_size_px(), 60.0);
+                assert_eq!(paragraph.children()[0].font_size_px(), 60.0);
+                assert_eq!(paragraph.children()[1].font_size_px(), 30.0);
+                assert_eq!(section.children()[1].font_size_px(), 30.0);
+                assert_eq!(styled.children()[1].font_size_px(), 40.0);
+            }
+
+            #[rstest]
+            fn resolves_em_lengths_with_the_stored_size() {
+                let stylesheet = css::parse("div { font-size: 10px; } p { font-size: 2em; width: 3em; height: 50%; }".to_string()).unwrap();
+                let root = html::parse("<div><p>x</p></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+                let paragraph = &styled.children()[0];
+                let context = paragraph.length_context(200.0);
+
+                assert_eq!(paragraph.value("width").unwrap().to_px(&context), Some(60.0));
+                assert_eq!(paragraph.value("height").unwrap().to_px(&context), Some(100.0));
+            }
+        }
+
+        describe "'style_tree_for_media' applies '@media' rules matching the context" {
+            #[rstest(color_scheme, width, expected_color, expected_display,
+                case(ColorScheme::Light, 800.0, "black", "flex"),
+                case(ColorScheme::Dark, 800.0, "white", "flex"),
+                case(ColorScheme::Dark, 500.0, "white", "block"),
+                case(ColorScheme::Light, 500.0, "black", "block"),
+            )]
+            fn test_media_rules(color_scheme: ColorScheme, width: f32, expected_color: &str, expected_display: &str) {
+                let stylesheet = css::parse("
+                    p { color: black; display: flex; }
+                    @media (prefers-color-scheme: dark) { p { color: white; } }
+                    @media screen and (max-width: 600px) { p { display: block; } }
+                ".to_string()).unwrap();
+                let root = html::parse("<div><p>text</p></div>".to_string()).unwrap();
+                let media = MediaContext::new(Viewport { width, height: 600.0 }, color_scheme);
+                let styled = style_tree_for_media(&root, &stylesheet, &media);
+                let paragraph = &styled.children()[0];
+
+                assert_eq!(paragraph.value("color"), Some(&Value::Keyword(expected_color.to_string())));
+                assert_eq!(paragraph.value("display"), Some(&Value::Keyword(expected_display.to_string())));
+            }
+
+            #[rstest]
+            fn style_tree_uses_the_default_context() {
+                let stylesheet = css::parse("p { color: black; } @media (prefers-color-scheme: dark) { p { color: white; } }".to_string()).unwrap();
+                let root = html::parse("<p>text</p>".to_string()).unwrap();
+
+                assert_eq!(style_tree(&root, &stylesheet).value("color"), Some(&Value::Keyword("black".to_string())));
+            }
+        }
+
+        describe "'style_tree_for_media' resolves viewport units against the viewport" {
+            #[rstest(width, height, expected_font_size, expected_width, expected_height,
+                case(1000.0, 500.0, 20.0, 500.0, 50.0),
+                case(400.0, 800.0, 8.0, 200.0, 80.0),
+            )]
+            fn test_viewport_units(width: f32, height: f32, expected_font_size: f32, expected_width: f32, expected_height: f32) {
+                let stylesheet = css::parse("p { font-size: 2vw; width: 50vw; height: 10vh; }".to_string()).unwrap();
+                let root = html::parse("<div><p>text</p></div>".to_string()).unwrap();
+                let media = MediaContext::new(Viewport { width, height }, ColorScheme::Light);
+                let styled = style_tree_for_media(&root, &stylesheet, &media);
+                let paragraph = &styled.children()[0];
+                let context = paragraph.length_context(0.0);
+
+                assert_eq!(paragraph.font_size_px(), expected_font_size);
+                assert_eq!(paragraph.value("width").unwrap().to_px(&context), Some(expected_width));
+                assert_eq!(paragraph.value("height").unwrap().to_px(&context), Some(expected_height));
+            }
+        }
+
+        describe "'style_tree' applies rules with combinators" {
+            #[rstest]
+            fn applies_descendant_and_child_rules() {
+                let stylesheet = css::parse("ul li { color: red; } ol > li { color: blue; }".to_string()).unwrap();
+                let root = Element::new("div")
+                    .child(Element::new("ul").child(Element::new("li").build()).build())
+                    .child(Element::new("ol").child(Element::new("li").build()).build())
+                    .child(Element::new("li").build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].children()[0].value("color"), Some(&Value::Keyword("red".to_string())));
+                assert_eq!(styled.children()[1].children()[0].value("color"), Some(&Value::Keyword("blue".to_string())));
+                assert_eq!(styled.children()[2].value("color"), None);
+            }
+        }
+
+        describe "'query_selector_all' returns matching descendant elements" {
+            #[rstest(selector, expected,
+                case("p", Vec::from(["a", "b", "c"])),
+                case(".x", Vec::from(["b", "c"])),
+                case("div > p", Vec::from(["a", "b"])),
+                case("span p.x", Vec::from(["c"])),
+                case("div", Vec::new()),
+            )]
+            fn test_query_selector_all(selector: &str, expected: Vec<&str>) {
+                let root = html::parse("<div><p id='a'>p</p><p id='b' class='x'></p><!-- <p class='x'></p> --><span><p id='c' class='x'></p></span></div>".to_string()).unwrap();
+
+                let ids: Vec<&str> = query_selector_all(&root, &Selector::parse(selector).unwrap())
+                    .into_iter()
+                    .map(|node| node.get_attribute("id").unwrap())
+                    .collect();
+
+                assert_eq!(ids, expected);
+            }
+        }
+
+        describe "'matches' compares tag names case-insensitively" {
+            #[rstest]
+            fn matches_tag_written_in_any_case() {
+                let mut html_parser = html::HTMLParser::new("<Div></Div>".to_string());
+                html_parser.preserve_tag_case(true);
+                let root = match html_parser.parse_partial() {
+                    Ok(html::PartialParse::Complete(root)) => root,
+                    result => panic!("expected a complete document, got {result:?}"),
+                };
+                let element_data = root.as_element().unwrap();
+
+                assert!(matches(&Selector::parse("div").unwrap(), element_data.into(), &[]));
+                assert!(matches(&Selector::parse("DIV").unwrap(), element_data.into(), &[]));
+                assert_eq!(root.to_html(), "<Div></Div>");
+            }
+        }
+
+        describe "'style_tree' resolves 'currentColor' to the element's color" {
+            #[rstest]
+            fn resolves_to_own_color() {
+                let stylesheet = css::parse("div { color: red; border-color: currentColor; background-color: currentcolor; }".to_string()).unwrap();
+                let root = Element::new("div").build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.value("border-color"), Some(&Value::keyword("red".to_string())));
+                assert_eq!(styled.value("background-color"), Some(&Value::keyword("red".to_string())));
+            }
+
+            #[rstest]
+            fn resolves_to_inherited_color() {
+                let stylesheet = css::parse("div { color: #123456; } p { border-color: currentColor; } span { color: currentColor; background-color: currentColor; }".to_string()).unwrap();
+                let root = Element::new("div")
+                    .child(Element::new("p").child(Element::new("span").build()).build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+                let paragraph = &styled.children()[0];
+
+                assert_eq!(paragraph.value("border-color"), Some(&Value::color(18, 52, 86)));
+                assert_eq!(paragraph.children()[0].value("color"), Some(&Value::color(18, 52, 86)));
+                assert_eq!(paragraph.children()[0].value("background-color"), Some(&Value::color(18, 52, 86)));
+            }
+
+            #[rstest]
+            fn falls_back_to_black() {
+                let stylesheet = css::parse("p { border-color: currentColor; }".to_string()).unwrap();
+                let root = Element::new("p").build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.value("border-color"), Some(&Value::color(0, 0, 0)));
+            }
+        }
+
+        describe "'style_tree' substitutes custom properties for 'var()'" {
+            #[rstest]
+            fn resolves_a_property_set_on_an_ancestor() {
+                let stylesheet = css::parse("div { --main-color: #123456; } p { color: var(--main-color); }".to_string()).unwrap();
+                let root = Element::new("div")
+                    .child(Element::new("section").child(Element::new("p").build()).build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+                let paragraph = &styled.children()[0].children()[0];
+
+                assert_eq!(paragraph.value("color"), Some(&Value::color(18, 52, 86)));
+                assert_eq!(paragraph.value("--main-color"), Some(&Value::Raw("#123456".to_string())));
+            }
+
+            #[rstest(stylesheet_data, expected,
+                case("div { --gap: 4px; } p { --gap: 2em; margin: var(--gap); }", Some(Value::size(2.0, Unit::Em))),
+                case("p { margin: var(--gap, 1px); }", Some(Value::size(1.0, Unit::Px))),
+                case("p { --gap: 1foo; margin: var(--gap, 1px); }", Some(Value::size(1.0, Unit::Px))),
+                case("div { --a: 3px; } p { --b: var(--a); margin: var(--b); }", Some(Value::size(3.0, Unit::Px))),
+                case("p { margin: var(--a, var(--b, 5px)); }", Some(Value::size(5.0, Unit::Px))),
+                case("p { margin: var(--gap); }", None),
+                case("p { --a: var(--b); --b: var(--a); margin: var(--a); }", None),
+            )]
+            fn test_fallback_and_undefined(stylesheet_data: &str, expected: Option<Value>) {
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                let root = Element::new("div").child(Element::new("p").build()).build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].value("margin"), expected.as_ref());
+            }
+
+            #[rstest]
+            fn resolves_current_color_from_a_variable() {
+                let stylesheet = css::parse("div { --fg: red; color: var(--fg); border-color: currentColor; }".to_string()).unwrap();
+                let root = Element::new("div").build();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.value("border-color"), Some(&Value::keyword("red".to_string())));
+            }
+        }
+
+        describe "'StyledNode::iter' walks the styled tree in pre-order" {
+            #[rstest]
+            fn visits_parents_before_children() {
+                let stylesheet = css::parse(".hidden { display: none; }".to_string()).unwrap();
+                let root = html::parse("<div id='a'><p id='b'>text<b id='c'></b></p><!-- x --><ul id='d' class='hidden'><li id='e'></li></ul></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                let order: Vec<String> = styled
+                    .iter()
+                    .map(|node| match node.node().node_type {
+                        NodeType::Text(ref text) => text.clone(),
+                        NodeType::Comment(_) => "comment".to_string(),
+                        NodeType::Element(_) => node.id().unwrap().to_string(),
+                    })
+                    .collect();
+
+                assert_eq!(order, Vec::from(["a", "b", "text", "c", "comment", "d", "e"]));
+            }
+
+            #[rstest]
+            fn collects_nodes_by_value() {
+                let stylesheet = css::parse(".hidden { display: none; }".to_string()).unwrap();
+                let root = html::parse("<div><p class='hidden'><span class='hidden'></span></p><ul class='hidden'></ul></div>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                let hidden: Vec<&str> = styled
+                    .iter()
+                    .filter(|node| node.value("display") == Some(&Value::keyword("none".to_string())))
+                    .filter_map(|node| node.tag_name())
+                    .collect();
+
+                assert_eq!(hidden, Vec::from(["p", "span", "ul"]));
+                assert_eq!(styled.children()[1].iter().count(), 1);
+            }
+        }
+
+        describe "'StyledNode' exposes the element it styles" {
+            #[rstest]
+            fn reads_tag_id_and_classes() {
+                let stylesheet = css::parse("p { color: red; }".to_string()).unwrap();
+                let root = Element::new("div")
+                    .attr("id", "main")
+                    .child(Element::new("p").class("lead").class("note").text("hello").build())
+                    .build();
+                let styled = style_tree(&root, &stylesheet);
+                let paragraph = &styled.children()[0];
+                let text = &paragraph.children()[0];
+
+                assert_eq!(styled.tag_name(), Some("div"));
+                assert_eq!(styled.id(), Some("main"));
+                assert!(!styled.has_class("lead"));
+
+                assert_eq!(paragraph.tag_name(), Some("p"));
+                assert_eq!(paragraph.id(), None);
+                assert!(paragraph.has_class("lead"));
+                assert!(paragraph.has_class("note"));
+                assert!(!paragraph.has_class("not"));
+
+                assert_eq!(text.tag_name(), None);
+                assert_eq!(text.id(), None);
+                assert!(!text.has_class("lead"));
+            }
+        }
+
+        describe "'to_json' serializes the styled tree" {
+            #[cfg(feature = "serde")]
+            #[rstest]
+            fn serializes_tags_attributes_and_values() {
+                let stylesheet = css::parse("p { color: #123456; margin: 1.5em; font-family: Arial, serif; display: block; }".to_string()).unwrap();
+                let root = Element::new("p").attr("id", "x").text("hi").build();
+                let styled = style_tree(&root, &stylesheet);
+
+                let json: serde_json::Value = serde_json::from_str(&to_json(&styled)).unwrap();
+
+                assert_eq!(json, serde_json::json!({
+                    "tag": "p",
+                    "attributes": { "id": "x" },
+                    "values": {
+                        "color": { "color": "#123456" },
+                        "margin": { "size": 1.5, "unit": "em" },
+                        "font-family": { "list": [{ "keyword": "Arial" }, { "keyword": "serif" }] },
+                        "display": { "keyword": "block" },
+                    },
+                    "children": [{ "text": "hi", "children": [] }],
+                }));
+            }
+        }
+
         describe "'matching_rules' returns rules matched for the element" {
             #[rstest(element_data, stylesheet_data, expected_rules,
                 case(
@@ -241,8 +1779,8 @@ mod tests {
                 ),
             )]
             fn matched_rules_for_the_element(element_data: ElementData, stylesheet_data: &str, expected_rules: Vec<Rule>) {
-                let stylesheet = css::parse(stylesheet_data.to_string());
-                let rules = matching_rules(&element_data, &stylesheet);
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                let rules = matching_rules((&element_data).into(), &[], &stylesheet, &MediaContext::default());
 
                 dbg!(&rules);
                 assert_eq!(rules.len(), expected_rules.len());
@@ -253,6 +1791,68 @@ mod tests {
             }
         }
 
+        describe "'matching_rule' uses the most specific matching selector of a rule" {
+            #[rstest]
+            fn matches_a_rule_once_with_its_highest_specificity() {
+                let element_data = ElementData::new("h1".to_string(), AttributeMap::from([("class".to_string(), "title".to_string())]));
+                let stylesheet = css::parse("h1, h1.title, h2, .title { color: red; }".to_string()).unwrap();
+                let rules = matching_rules((&element_data).into(), &[], &stylesheet, &MediaContext::default());
+
+                assert_eq!(rules.len(), 1);
+                assert_eq!(rules[0].0, (0, 0, 1, 1));
+            }
+
+            #[rstest(stylesheet_data, expected,
+                case("h1, h1.title { color: red; } .title { color: blue; }", "red"),
+                case(".title { color: blue; } h1, h1.title { color: red; }", "red"),
+                case("h2, h1 { color: red; } .title { color: blue; }", "blue"),
+            )]
+            fn orders_the_rule_by_that_selector(stylesheet_data: &str, expected: &str) {
+                let element_data = ElementData::new("h1".to_string(), AttributeMap::from([("class".to_string(), "title".to_string())]));
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+
+                assert_eq!(
+                    specified_values((&element_data).into(), &[], &stylesheet, &MediaContext::default()).get("color"),
+                    Some(&Value::Keyword(expected.to_string()))
+                );
+            }
+        }
+
+        describe "'specified_values' applies the style attribute above any selector" {
+            #[rstest]
+            fn inline_style_beats_an_id_rule() {
+                let element_data = ElementData::new("p".to_string(), AttributeMap::from([
+                    ("id".to_string(), "main".to_string()),
+                    ("style".to_string(), "color: green; margin: 0".to_string()),
+                ]));
+                let stylesheet = css::parse("#main { color: red; padding: 0; } p { margin: 1px; }".to_string()).unwrap();
+                let values = specified_values((&element_data).into(), &[], &stylesheet, &MediaContext::default());
+
+                assert_eq!(values.get("color"), Some(&Value::Keyword("green".to_string())));
+                assert_eq!(values.get("margin"), Some(&Value::Number(0.0)));
+                assert_eq!(values.get("padding"), Some(&Value::Number(0.0)));
+            }
+
+            #[rstest]
+            fn invalid_inline_style_is_ignored() {
+                let element_data = ElementData::new("p".to_string(), AttributeMap::from([("style".to_string(), "color green".to_string())]));
+                let stylesheet = css::parse("p { color: red; }".to_string()).unwrap();
+                let values = specified_values((&element_data).into(), &[], &stylesheet, &MediaContext::default());
+
+                assert_eq!(values.get("color"), Some(&Value::Keyword("red".to_string())));
+            }
+
+            #[rstest]
+            fn style_tree_does_not_share_values_across_inline_styles() {
+                let stylesheet = css::parse("li { color: red; }".to_string()).unwrap();
+                let root = html::parse("<ul><li style='color: blue'></li><li></li></ul>".to_string()).unwrap();
+                let styled = style_tree(&root, &stylesheet);
+
+                assert_eq!(styled.children()[0].value("color"), Some(&Value::Keyword("blue".to_string())));
+                assert_eq!(styled.children()[1].value("color"), Some(&Value::Keyword("red".to_string())));
+            }
+        }
+
         describe "'specified_values' returns a propaty map for the element in specificity order of rules" {
             #[rstest(element_data, stylesheet_data, expected_property_map,
                 case(
@@ -288,8 +1888,39 @@ mod tests {
                 ),
             )]
             fn matched_property_map_for_the_element_in_specificity_order(element_data: ElementData, stylesheet_data: &str, expected_property_map: PropertyMap) {
-                let stylesheet = css::parse(stylesheet_data.to_string());
-                assert_eq!(specified_values(&element_data, &stylesheet), expected_property_map);
+                let stylesheet = css::parse(stylesheet_data.to_string()).unwrap();
+                assert_eq!(specified_values((&element_data).into(), &[], &stylesheet, &MediaContext::default()), expected_property_map);
+            }
+        }
+
+        describe "'style_tree' shares computed values between identical elements" {
+            #[rstest]
+            fn same_signature_regardless_of_class_order() {
+                let a = ElementData::new("li".to_string(), AttributeMap::from([("class".to_string(), "x y".to_string())]));
+                let b = ElementData::new("li".to_string(), AttributeMap::from([("class".to_string(), "y x".to_string())]));
+                let c = ElementData::new("li".to_string(), AttributeMap::from([("class".to_string(), "x".to_string())]));
+
+                assert!(ElementSignature::new(&a) == ElementSignature::new(&b));
+                assert!(ElementSignature::new(&a) != ElementSignature::new(&c));
+            }
+
+            #[rstest]
+            fn identical_elements_are_cascaded_once() {
+                let stylesheet = css::parse("li { display: block; } .done { color: gray; }".to_string()).unwrap();
+                let mut root = Element::new("ul");
+                for i in 0..100 {
+                    let item = if i % 2 == 0 { Element::new("li").class("done") } else { Element::new("li") };
+                    root = root.child(item.build());
+                }
+                let root = root.build();
+
+                let mut cache = StyleCache::new(&stylesheet);
+                let styled = style_node(&root, root.as_element().map(ElementRef::root), &stylesheet, &MediaContext::default(), &mut Vec::new(), &mut cache, &InheritedValues::default());
+
+                assert_eq!(cache.entries.len(), 3);
+                assert_eq!(styled.children()[0].value("color"), Some(&Value::Keyword("gray".to_string())));
+                assert_eq!(styled.children()[1].value("color"), None);
+                assert_eq!(styled.children()[99].value("display"), Some(&Value::Keyword("block".to_string())));
             }
         }
     }